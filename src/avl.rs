@@ -0,0 +1,267 @@
+use std::cmp::Ordering;
+
+use crate::balance::{Balance, Sealed};
+use crate::{count_descent, Bst, Monoid};
+
+/// AVL tree: every node keeps the heights of its two subtrees within one of
+/// each other, rotating on insert and remove as needed. The height of a tree
+/// of n elements is thus at most about 1.44 * log2(n).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Avl;
+
+impl Sealed for Avl {}
+
+impl Balance for Avl {
+    fn insert<E, A: Monoid<E>, F, M, R>(
+        root: &mut Option<Box<Bst<E, A>>>,
+        new_val: E,
+        mut locate: F,
+        merge: M,
+    ) -> Option<R>
+    where
+        F: FnMut(&E, &Bst<E, A>) -> Ordering,
+        M: FnOnce(&mut E, E) -> R,
+    {
+        count_descent();
+        insert(root, new_val, &mut locate, merge)
+    }
+
+    fn take<E, A: Monoid<E>, F>(root: &mut Option<Box<Bst<E, A>>>, mut locate: F) -> Option<E>
+    where
+        F: FnMut(&Bst<E, A>) -> Ordering,
+    {
+        count_descent();
+        take(root, &mut locate)
+    }
+
+    fn take_first<E, A: Monoid<E>>(root: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+        count_descent();
+        take_first(root)
+    }
+
+    fn take_last<E, A: Monoid<E>>(root: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+        count_descent();
+        take_last(root)
+    }
+
+    fn join<E, A: Monoid<E>>(
+        left: Option<Box<Bst<E, A>>>,
+        mid: E,
+        right: Option<Box<Bst<E, A>>>,
+    ) -> Box<Bst<E, A>> {
+        join(left, mid, right)
+    }
+}
+
+/// Recursively inserts [new_val] below [link] where [locate] directs,
+/// rebalancing each node on the way back up if the value was inserted, or
+/// merges it into an equal value with [merge] and only updates the aggregates
+/// above that.
+fn insert<E, A: Monoid<E>, F, M, R>(
+    link: &mut Option<Box<Bst<E, A>>>,
+    new_val: E,
+    locate: &mut F,
+    merge: M,
+) -> Option<R>
+where
+    F: FnMut(&E, &Bst<E, A>) -> Ordering,
+    M: FnOnce(&mut E, E) -> R,
+{
+    let node = match link {
+        Some(node) => node,
+        None => {
+            *link = Some(Box::new(Bst::with_children(new_val, None, None)));
+            return None;
+        }
+    };
+    let merged = match locate(&new_val, node) {
+        Ordering::Less => insert(&mut node.left, new_val, locate, merge),
+        Ordering::Greater => insert(&mut node.right, new_val, locate, merge),
+        Ordering::Equal => Some(merge(&mut node.value, new_val)),
+    };
+    match merged {
+        Some(_) => node.update_aggregate(),
+        None => rebalance(link),
+    }
+    merged
+}
+
+/// Recursively removes the element that [locate] finds below [link],
+/// rebalancing each node on the way back up if an element was removed.
+fn take<E, A: Monoid<E>, F>(link: &mut Option<Box<Bst<E, A>>>, locate: &mut F) -> Option<E>
+where
+    F: FnMut(&Bst<E, A>) -> Ordering,
+{
+    let node = link.as_mut()?;
+    let taken = match locate(node) {
+        Ordering::Less => take(&mut node.left, locate),
+        Ordering::Greater => take(&mut node.right, locate),
+        Ordering::Equal => {
+            let node = link.take()?;
+            let Bst {
+                value, left, right, ..
+            } = *node;
+            *link = match (left.into_inner(), right.into_inner()) {
+                (None, right) => right,
+                (left, None) => left,
+                (left, mut right) => {
+                    let successor = take_first(&mut right).expect("right subtree is not empty");
+                    Some(Box::new(Bst::with_children(successor, left, right)))
+                }
+            };
+            Some(value)
+        }
+    };
+    if taken.is_some() {
+        rebalance(link);
+    }
+    taken
+}
+
+/// Removes the least element below [link], rebalancing on the way back up.
+fn take_first<E, A: Monoid<E>>(link: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+    let node = link.as_mut()?;
+    if node.left.is_some() {
+        let taken = take_first(&mut node.left);
+        rebalance(link);
+        taken
+    } else {
+        let node = link.take()?;
+        let Bst { value, right, .. } = *node;
+        *link = right.into_inner();
+        Some(value)
+    }
+}
+
+/// Removes the greatest element below [link], rebalancing on the way back up.
+fn take_last<E, A: Monoid<E>>(link: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+    let node = link.as_mut()?;
+    if node.right.is_some() {
+        let taken = take_last(&mut node.right);
+        rebalance(link);
+        taken
+    } else {
+        let node = link.take()?;
+        let Bst { value, left, .. } = *node;
+        *link = left.into_inner();
+        Some(value)
+    }
+}
+
+/// Joins [left], [mid] and [right]. If one side is more than one level taller
+/// than the other, [mid] and the shorter side are joined recursively into the
+/// inner spine of the taller side, down to a subtree of about their height,
+/// rebalancing on the way back up. Takes time proportional to the difference
+/// in heights.
+fn join<E, A: Monoid<E>>(
+    left: Option<Box<Bst<E, A>>>,
+    mid: E,
+    right: Option<Box<Bst<E, A>>>,
+) -> Box<Bst<E, A>> {
+    let (left_height, right_height) = (Bst::height_of(&left), Bst::height_of(&right));
+    if left_height > right_height + 1 {
+        let mut node = left.expect("taller subtree is not empty");
+        let inner = node.right.take();
+        *node.right = Some(join(inner, mid, right));
+        balance(node)
+    } else if right_height > left_height + 1 {
+        let mut node = right.expect("taller subtree is not empty");
+        let inner = node.left.take();
+        *node.left = Some(join(left, mid, inner));
+        balance(node)
+    } else {
+        Box::new(Bst::with_children(mid, left, right))
+    }
+}
+
+/// Restores the AVL property at the root of [link], assuming both of its
+/// subtrees are AVL trees whose heights differ by at most two, and updates
+/// the root's height.
+fn rebalance<E, A: Monoid<E>>(link: &mut Option<Box<Bst<E, A>>>) {
+    if let Some(node) = link.take() {
+        *link = Some(balance(node));
+    }
+}
+
+/// Balance factor of [node]: height of its left subtree minus that of its right
+fn balance_factor<E, A>(node: &Bst<E, A>) -> isize {
+    Bst::height_of(&node.left) as isize - Bst::height_of(&node.right) as isize
+}
+
+/// Rotates [node] as needed so that its balance factor is within one,
+/// returning the new subtree root.
+fn balance<E, A: Monoid<E>>(mut node: Box<Bst<E, A>>) -> Box<Bst<E, A>> {
+    node.update();
+    match balance_factor(&node) {
+        2 => {
+            let left = node.left.take().expect("left-heavy node has a left child");
+            *node.left = Some(if balance_factor(&left) < 0 {
+                Bst::rotate_left(left)
+            } else {
+                left
+            });
+            Bst::rotate_right(node)
+        }
+        -2 => {
+            let right = node
+                .right
+                .take()
+                .expect("right-heavy node has a right child");
+            *node.right = Some(if balance_factor(&right) > 0 {
+                Bst::rotate_right(right)
+            } else {
+                right
+            });
+            Bst::rotate_left(node)
+        }
+        _ => node,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{avl_height_bound, check_avl};
+    use crate::{AvlSet, BstIter};
+
+    #[test]
+    fn avl_stays_balanced_on_sorted_input() {
+        const N: usize = 2_000;
+        let mut set = AvlSet::new();
+        for value in 0..N {
+            assert!(set.insert(value));
+            assert!(check_avl(&set.root) <= avl_height_bound(set.len()));
+        }
+        for value in (N..2 * N).rev() {
+            assert!(set.insert(value));
+            assert!(check_avl(&set.root) <= avl_height_bound(set.len()));
+        }
+        assert!(set.iter().copied().eq(0..2 * N));
+
+        // Removing every other value, then a run from each end, rotates on the
+        // way back up from each removal
+        for value in (0..2 * N).step_by(2) {
+            assert!(set.remove(&value));
+            assert!(check_avl(&set.root) <= avl_height_bound(set.len()));
+        }
+        for _ in 0..N / 4 {
+            assert!(set.pop_first().is_some());
+            assert!(set.pop_last().is_some());
+            assert!(check_avl(&set.root) <= avl_height_bound(set.len()));
+        }
+        let expected = (0..2 * N).skip(N / 2).take(N).filter(|v| v % 2 == 1);
+        assert!(set.iter().copied().eq(expected));
+        assert_eq!(set.len(), N / 2);
+    }
+
+    #[test]
+    fn avl_join_rebalances() {
+        for (split, end) in [(1000, 1010), (10, 1010), (500, 1000)] {
+            let left = AvlSet::from_sorted_iter(0..split).root;
+            let right = AvlSet::from_sorted_iter(split + 1..end).root;
+            let joined = Some(Avl::join(left, split, right));
+            assert!(check_avl(&joined) <= avl_height_bound(end));
+            assert!(BstIter::from_root(joined.as_deref()).copied().eq(0..end));
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::cmp::Ordering;
+use std::mem;
+use std::ops;
+
+use crate::{after_range, before_range, Bst, IntoIter, Monoid};
+
+mod private {
+    /// Prevents Balance from being implemented outside this crate, since
+    /// implementations need access to the internals of Bst.
+    pub trait Sealed {}
+}
+
+pub(crate) use private::Sealed;
+
+/// Strategy used by a TreeSet to keep its tree balanced (or not) as elements
+/// are inserted and removed. Read-only operations such as lookup and
+/// iteration are shared by every strategy.
+pub trait Balance: Sealed {
+    /// Inserts [new_val] into the tree at [root], descending as directed by
+    /// [locate], which is shown [new_val] and each node in turn and reports Less
+    /// to go left, Greater to go right or Equal if the node holds an element
+    /// equal to [new_val]. Returns None if inserted; if such an element is
+    /// already present, [new_val] is instead handed to [merge] along with it,
+    /// on the same descent, and the result of [merge] is returned.
+    #[doc(hidden)]
+    fn insert<E, A: Monoid<E>, F, M, R>(
+        root: &mut Option<Box<Bst<E, A>>>,
+        new_val: E,
+        locate: F,
+        merge: M,
+    ) -> Option<R>
+    where
+        F: FnMut(&E, &Bst<E, A>) -> Ordering,
+        M: FnOnce(&mut E, E) -> R;
+
+    /// Removes the element of the tree at [root] whose node [locate] reports as
+    /// Equal, returning it if found. [locate] is shown the nodes of a single
+    /// descent from the root in turn, and reports Less to go left or Greater to
+    /// go right; it may steer by the node's value or by the sizes of its subtrees.
+    #[doc(hidden)]
+    fn take<E, A: Monoid<E>, F>(root: &mut Option<Box<Bst<E, A>>>, locate: F) -> Option<E>
+    where
+        F: FnMut(&Bst<E, A>) -> Ordering;
+
+    /// Removes the least element of the tree at [root], returning it if the
+    /// tree is not empty.
+    #[doc(hidden)]
+    fn take_first<E, A: Monoid<E>>(root: &mut Option<Box<Bst<E, A>>>) -> Option<E>;
+
+    /// Removes the greatest element of the tree at [root], returning it if the
+    /// tree is not empty.
+    #[doc(hidden)]
+    fn take_last<E, A: Monoid<E>>(root: &mut Option<Box<Bst<E, A>>>) -> Option<E>;
+
+    /// Joins [left], [mid] and [right] into one tree, given that every element
+    /// of [left] precedes [mid] and [mid] precedes every element of [right].
+    #[doc(hidden)]
+    fn join<E, A: Monoid<E>>(
+        left: Option<Box<Bst<E, A>>>,
+        mid: E,
+        right: Option<Box<Bst<E, A>>>,
+    ) -> Box<Bst<E, A>>;
+
+    /// Splits the tree at [root], leaving the elements that [locate] reports as
+    /// Greater (those that precede the key it describes) at [root] and returning
+    /// a tree of the rest. The path to the key is taken apart top-down and its
+    /// pieces are joined back up bottom-up, which in a balanced tree takes
+    /// O(height) time in total.
+    #[doc(hidden)]
+    fn split<E, A: Monoid<E>, F>(
+        root: &mut Option<Box<Bst<E, A>>>,
+        mut locate: F,
+    ) -> Option<Box<Bst<E, A>>>
+    where
+        F: FnMut(&E) -> Ordering,
+    {
+        // Elements on the path, each with its subtree on the same side of the split
+        let mut before = vec![];
+        let mut after = vec![];
+        let mut node = root.take();
+        while let Some(current) = node {
+            let Bst {
+                value, left, right, ..
+            } = *current;
+            if locate(&value) == Ordering::Greater {
+                before.push((left.into_inner(), value));
+                node = right.into_inner();
+            } else {
+                after.push((value, right.into_inner()));
+                node = left.into_inner();
+            }
+        }
+        for (subtree, value) in before.into_iter().rev() {
+            *root = Some(Self::join(subtree, value, root.take()));
+        }
+        let mut rest = None;
+        for (value, subtree) in after.into_iter().rev() {
+            rest = Some(Self::join(rest, value, subtree));
+        }
+        rest
+    }
+
+    /// Removes the elements of the tree at [root] that lie within [range], using
+    /// [cmp] to compare a bound with an element, and returns a tree of them.
+    /// The tree is split at both ends of the range and the outer parts are
+    /// joined, so this takes O(height) time in a balanced tree.
+    #[doc(hidden)]
+    fn take_range<E, A: Monoid<E>, Q, R, F>(
+        root: &mut Option<Box<Bst<E, A>>>,
+        range: &R,
+        mut cmp: F,
+    ) -> Option<Box<Bst<E, A>>>
+    where
+        Q: ?Sized,
+        R: ops::RangeBounds<Q>,
+        F: FnMut(&Q, &E) -> Ordering,
+    {
+        let as_locate = |before: bool| {
+            if before {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            }
+        };
+        let mut within = Self::split(root, |v| as_locate(before_range(range, &mut cmp, v)));
+        let mut after = Self::split(&mut within, |v| as_locate(!after_range(range, &mut cmp, v)));
+        if let Some(mid) = Self::take_first(&mut after) {
+            *root = Some(Self::join(root.take(), mid, after));
+        }
+        within
+    }
+
+    /// Moves every element of the tree at [other] into the tree at [root], using
+    /// [cmp] to order elements and [resolve] to combine an element of [root]
+    /// with an equal element of [other]. If every element of one tree precedes
+    /// every element of the other, they are joined in O(height) time; otherwise
+    /// they are merged and rebuilt in O(n + m) time.
+    #[doc(hidden)]
+    fn append<E, A: Monoid<E>, F, R>(
+        root: &mut Option<Box<Bst<E, A>>>,
+        other: &mut Option<Box<Bst<E, A>>>,
+        mut cmp: F,
+        mut resolve: R,
+    ) where
+        F: FnMut(&E, &E) -> Ordering,
+        R: FnMut(E, E) -> E,
+    {
+        if other.is_none() {
+            return;
+        }
+        if root.is_none() {
+            mem::swap(root, other);
+            return;
+        }
+        let precedes = |a: &Option<Box<Bst<E, A>>>, b: &Option<Box<Bst<E, A>>>, cmp: &mut F| {
+            let last = Bst::last_of(a.as_deref()).expect("tree is not empty");
+            let first = Bst::first_of(b.as_deref()).expect("tree is not empty");
+            cmp(last, first) == Ordering::Less
+        };
+        if precedes(root, other, &mut cmp) {
+            let mid = Self::take_first(other).expect("tree is not empty");
+            *root = Some(Self::join(root.take(), mid, other.take()));
+        } else if precedes(other, root, &mut cmp) {
+            let mid = Self::take_first(root).expect("tree is not empty");
+            *root = Some(Self::join(other.take(), mid, root.take()));
+        } else {
+            let mut ours = IntoIter::from_root(root.take()).peekable();
+            let mut theirs = IntoIter::from_root(other.take()).peekable();
+            let mut merged = Vec::with_capacity(ours.len() + theirs.len());
+            loop {
+                let order = match (ours.peek(), theirs.peek()) {
+                    (None, None) => break,
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (Some(a), Some(b)) => cmp(a, b),
+                };
+                merged.extend(match order {
+                    Ordering::Less => ours.next(),
+                    Ordering::Greater => theirs.next(),
+                    Ordering::Equal => ours.next().zip(theirs.next()).map(|(a, b)| resolve(a, b)),
+                });
+            }
+            let len = merged.len();
+            *root = Bst::build_balanced(&mut merged.into_iter(), len);
+        }
+    }
+}
+
+/// Plain binary search tree: elements are placed where the search for them
+/// ends and the tree is never restructured, so its height depends on the
+/// insertion order.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Unbalanced;
+
+impl Sealed for Unbalanced {}
+
+impl Balance for Unbalanced {
+    fn insert<E, A: Monoid<E>, F, M, R>(
+        root: &mut Option<Box<Bst<E, A>>>,
+        new_val: E,
+        locate: F,
+        merge: M,
+    ) -> Option<R>
+    where
+        F: FnMut(&E, &Bst<E, A>) -> Ordering,
+        M: FnOnce(&mut E, E) -> R,
+    {
+        Bst::insert_by(root, new_val, locate, merge)
+    }
+
+    fn take<E, A: Monoid<E>, F>(root: &mut Option<Box<Bst<E, A>>>, locate: F) -> Option<E>
+    where
+        F: FnMut(&Bst<E, A>) -> Ordering,
+    {
+        Bst::take_by(root, locate)
+    }
+
+    fn take_first<E, A: Monoid<E>>(root: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+        Bst::take_first(root)
+    }
+
+    fn take_last<E, A: Monoid<E>>(root: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+        Bst::take_last(root)
+    }
+
+    fn join<E, A: Monoid<E>>(
+        left: Option<Box<Bst<E, A>>>,
+        mid: E,
+        right: Option<Box<Bst<E, A>>>,
+    ) -> Box<Bst<E, A>> {
+        Box::new(Bst::with_children(mid, left, right))
+    }
+}
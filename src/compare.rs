@@ -0,0 +1,138 @@
+use std::cmp;
+use std::cmp::Ordering;
+
+/// A total order on values of type T, used by trees in place of T's own Ord.
+/// Implemented by Natural (which defers to Ord) and by any closure or function
+/// of type Fn(&T, &T) -> Ordering, e.g. `|a: &f64, b: &f64| a.total_cmp(b)`.
+pub trait Compare<T: ?Sized> {
+    /// Compares [a] with [b], as Ord::cmp would.
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// Comparator using the natural (Ord) ordering of the values. This is the
+/// default comparator of trees, and the only one for which lookups may use a
+/// borrowed form of the element type.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Natural;
+
+impl<T: cmp::Ord + ?Sized> Compare<T> for Natural {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Allows closures and functions to be used as comparators
+impl<T: ?Sized, F: Fn(&T, &T) -> Ordering> Compare<T> for F {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Avl, AvlSet, Balance, RbMap, RedBlack, TreeSet, Unbalanced};
+
+    /// Orders numbers from greatest to least
+    fn reversed(a: &i32, b: &i32) -> Ordering {
+        b.cmp(a)
+    }
+
+    /// Orders words alphabetically, ignoring case
+    fn caseless(a: &String, b: &String) -> Ordering {
+        let fold = |word: &String| {
+            word.chars()
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        };
+        fold(a).cmp(&fold(b))
+    }
+
+    /// Runs a set of B through each ordered operation under a reversed comparator
+    fn check_reversed<B: Balance>() {
+        use std::ops::Bound::{Excluded, Included};
+
+        let mut set: TreeSet<i32, B, fn(&i32, &i32) -> Ordering> =
+            TreeSet::with_comparator(reversed);
+        for value in [5, 1, 9, 3, 7, 3] {
+            set.insert(value);
+        }
+        assert!(!set.insert(9));
+        assert!(set.iter().copied().eq([9, 7, 5, 3, 1]));
+        assert!(set.iter().rev().copied().eq([1, 3, 5, 7, 9]));
+        assert_eq!(set.get(&7), Some(&7));
+        assert!(!set.contains(&4));
+        assert_eq!(set.first(), Some(&9));
+        assert_eq!(set.last(), Some(&1));
+
+        // A range runs from its start to its end in the comparator's order
+        assert!(set.range((Included(8), Excluded(3))).copied().eq([7, 5]));
+        assert!(set.range((Included(7), Included(3))).copied().eq([7, 5, 3]));
+        assert!(set.range(..5).copied().eq([9, 7]));
+        assert_eq!(set.range(3..8).len(), 0);
+
+        // The floor of a key is the last element at or before it in that order
+        assert_eq!(set.floor(&6), Some(&7));
+        assert_eq!(set.floor(&5), Some(&5));
+        assert_eq!(set.floor(&10), None);
+        assert_eq!(set.ceiling(&6), Some(&5));
+        assert_eq!(set.ceiling(&0), None);
+        assert_eq!(set.predecessor(&5), Some(&7));
+        assert_eq!(set.successor(&5), Some(&3));
+
+        assert!(set.remove(&5));
+        assert!(!set.remove(&5));
+        assert_eq!(set.pop_first(), Some(9));
+        assert_eq!(set.pop_last(), Some(1));
+        assert!(set.iter().copied().eq([7, 3]));
+    }
+
+    #[test]
+    fn reversed_comparator_orders_sets() {
+        check_reversed::<Unbalanced>();
+        check_reversed::<Avl>();
+        check_reversed::<RedBlack>();
+    }
+
+    #[test]
+    fn caseless_comparator_orders_maps() {
+        let word = |word: &str| word.to_string();
+        let mut map = RbMap::with_comparator(caseless);
+        for (key, value) in [("banana", 1), ("Apple", 2), ("cherry", 3), ("apricot", 4)] {
+            assert_eq!(map.insert(word(key), value), None);
+        }
+        // A key equal but for case replaces the value and keeps the first key
+        assert_eq!(map.insert(word("BANANA"), 5), Some(1));
+        assert!(map.keys().eq(["Apple", "apricot", "banana", "cherry"]));
+        assert_eq!(map.get(&word("APPLE")), Some(&2));
+        assert!(map.contains_key(&word("Cherry")));
+        assert!(!map.contains_key(&word("date")));
+
+        assert_eq!(
+            map.floor_key_value(&word("B")),
+            Some((&word("apricot"), &4))
+        );
+        assert_eq!(
+            map.ceiling_key_value(&word("B")),
+            Some((&word("banana"), &5))
+        );
+        let keys: Vec<&String> = map.range(word("AP")..word("C")).map(|(k, _)| k).collect();
+        assert_eq!(keys, ["Apple", "apricot", "banana"]);
+
+        *map.entry(word("CHERRY")).or_insert(0) += 10;
+        assert_eq!(map.remove(&word("APRICOT")), Some(4));
+        assert!(map.iter().eq([
+            (&word("Apple"), &2),
+            (&word("banana"), &5),
+            (&word("cherry"), &13)
+        ]));
+
+        let mut set = AvlSet::with_comparator(caseless);
+        for value in ["b", "B", "a", "C", "c"] {
+            set.insert(word(value));
+        }
+        assert!(set.iter().eq(["a", "b", "C"]));
+        assert!(set.remove(&word("c")));
+        assert!(set.iter().eq(["a", "b"]));
+    }
+}
@@ -1,64 +1,298 @@
+use std::borrow::Borrow;
 use std::cmp;
 use std::cmp::Ordering;
-use std::convert;
 use std::fmt;
+use std::iter;
+use std::mem;
 use std::ops;
 
-/// A binary search tree with element type E
-#[derive(Clone, Debug)]
-pub struct Bst<E> {
+mod avl;
+mod balance;
+mod compare;
+mod map;
+mod monoid;
+mod multiset;
+mod rb;
+mod set;
+#[cfg(test)]
+mod test_util;
+
+pub use avl::Avl;
+pub use balance::{Balance, Unbalanced};
+pub use compare::{Compare, Natural};
+pub use map::{
+    AvlMap, BstMap, Entry, Keys, MapExtractIf, MapIter, MapIterMut, OccupiedEntry, RbMap, TreeMap,
+    VacantEntry, Values, ValuesMut,
+};
+pub use monoid::{Count, Max, Min, Monoid, Sum};
+pub use multiset::{AvlMultiset, BstMultiset, MultisetIter, RbMultiset, TreeMultiset};
+pub use rb::RedBlack;
+pub use set::{
+    AvlSet, BstSet, Difference, ExtractIf, Intersection, RbSet, SymmetricDifference, TreeSet, Union,
+};
+
+/// A binary search tree with element type E, each node of which caches the
+/// aggregate A of its subtree (nothing, by default; see Monoid)
+pub struct Bst<E, A = ()> {
     value: E,
-    left: Option<Box<Bst<E>>>,
-    right: Option<Box<Bst<E>>>,
+    left: Link<E, A>,
+    right: Link<E, A>,
+    /// Height of the subtree rooted at this node (1 for a leaf)
+    height: usize,
+    /// Number of values in the subtree rooted at this node (1 for a leaf)
+    size: usize,
+    /// Whether this node is red; only meaningful in RedBlack mode.
+    red: bool,
+    /// Aggregate of the values in the subtree rooted at this node
+    aggregate: A,
+}
+
+/// Owning link from a node to one of its children. Dereferences to the
+/// (possibly empty) boxed subtree, which is what the link helpers of Bst work
+/// on, but tears that subtree down iteratively when dropped, so that dropping
+/// a degenerate tree cannot overflow the stack.
+pub(crate) struct Link<E, A = ()>(Option<Box<Bst<E, A>>>);
+
+/// Methods for Link, parameterized over element type of the BST
+impl<E, A> Link<E, A> {
+    /// Detaches the subtree from this link and returns it
+    pub(crate) fn into_inner(mut self) -> Option<Box<Bst<E, A>>> {
+        self.0.take()
+    }
+}
+
+impl<E, A> ops::Deref for Link<E, A> {
+    type Target = Option<Box<Bst<E, A>>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<E, A> ops::DerefMut for Link<E, A> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Detaches the children of each node before the node itself is dropped,
+/// keeping the nodes still to be dropped on a heap-allocated stack
+impl<E, A> Drop for Link<E, A> {
+    fn drop(&mut self) {
+        let mut nodes: Vec<Box<Bst<E, A>>> = self.0.take().into_iter().collect();
+        while let Some(mut node) = nodes.pop() {
+            nodes.extend(node.left.take());
+            nodes.extend(node.right.take());
+        }
+    }
+}
+
+/// Clones a BST without recursion. Nodes are copied in post-order, so that
+/// when a node is reached, copies of its subtrees are on top of a stack.
+impl<E: Clone, A: Clone> Clone for Bst<E, A> {
+    fn clone(&self) -> Self {
+        let mut pending = vec![(self, false)];
+        let mut copied: Vec<Bst<E, A>> = vec![];
+        while let Some((node, children_copied)) = pending.pop() {
+            if !children_copied {
+                pending.push((node, true));
+                pending.extend(node.right.as_deref().map(|right| (right, false)));
+                pending.extend(node.left.as_deref().map(|left| (left, false)));
+                continue;
+            }
+            let mut copy_child = |child: &Link<E, A>| {
+                child
+                    .as_ref()
+                    .map(|_| Box::new(copied.pop().expect("subtree was copied")))
+            };
+            let right = copy_child(&node.right);
+            let left = copy_child(&node.left);
+            copied.push(Bst {
+                value: node.value.clone(),
+                left: Link(left),
+                right: Link(right),
+                height: node.height,
+                size: node.size,
+                red: node.red,
+                aggregate: node.aggregate.clone(),
+            });
+        }
+        copied.pop().expect("root was copied")
+    }
 }
 
 /// Print space-separated in-order traversal of a BST
-impl<E: fmt::Display> fmt::Display for Bst<E> {
+impl<E: fmt::Display, A> fmt::Display for Bst<E, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(left) = &self.left {
-            write!(f, "{} ", left)?;
-        }
-        write!(f, "{}", self.value)?;
-        if let Some(right) = &self.right {
-            write!(f, " {}", right)?;
+        for (i, value) in BstIter::new(self).enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", value)?;
         }
         Ok(())
     }
 }
 
+/// Formats a BST as the set of its values, in order, e.g. `{1, 2, 3}`. The
+/// values are visited with BstIter, so formatting a degenerate tree cannot
+/// overflow the stack.
+impl<E: fmt::Debug, A> fmt::Debug for Bst<E, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(BstIter::new(self)).finish()
+    }
+}
+
+/// Marks the start of a descent of a tree in search of a single value or
+/// position. Test builds count these, so that tests can check how many times
+/// an operation walks the tree (see test_util::descents); otherwise this does
+/// nothing.
+pub(crate) fn count_descent() {
+    #[cfg(test)]
+    test_util::DESCENTS.with(|descents| descents.set(descents.get() + 1));
+}
+
+/// Whether [value] precedes every value within [range], using [cmp] to compare
+/// a bound with a value
+pub(crate) fn before_range<E, Q, R, F>(range: &R, cmp: &mut F, value: &E) -> bool
+where
+    Q: ?Sized,
+    R: ops::RangeBounds<Q>,
+    F: FnMut(&Q, &E) -> Ordering,
+{
+    match range.start_bound() {
+        ops::Bound::Included(start) => cmp(start, value) == Ordering::Greater,
+        ops::Bound::Excluded(start) => cmp(start, value) != Ordering::Less,
+        ops::Bound::Unbounded => false,
+    }
+}
+
+/// Whether [value] follows every value within [range], using [cmp] to compare
+/// a bound with a value
+pub(crate) fn after_range<E, Q, R, F>(range: &R, cmp: &mut F, value: &E) -> bool
+where
+    Q: ?Sized,
+    R: ops::RangeBounds<Q>,
+    F: FnMut(&Q, &E) -> Ordering,
+{
+    match range.end_bound() {
+        ops::Bound::Included(end) => cmp(end, value) == Ordering::Less,
+        ops::Bound::Excluded(end) => cmp(end, value) != Ordering::Greater,
+        ops::Bound::Unbounded => false,
+    }
+}
+
 /// Iterator for a BST, parameterized over lifetime and element type of the BST.
 /// Design based on https://medium.com/algorithm-problems/binary-search-tree-iterator-19615ec585a
 #[derive(Debug)]
-pub struct BstIter<'a, E> {
+pub struct BstIter<'a, E, A = ()> {
     /// Stack of references to tree nodes that have the current node in their left
     /// subtree. Equivalently, the path from the root to the current node, skipping nodes
     /// that have already been seen. Current node is the top of the stack.
-    nodes: Vec<&'a Bst<E>>,
+    nodes: Vec<&'a Bst<E, A>>,
+    /// Mirror image of nodes used by next_back: references to tree nodes that have
+    /// the current back node in their right subtree. Current back node is the top.
+    back: Vec<&'a Bst<E, A>>,
+    /// Number of values left to yield from either end. Iteration stops when it
+    /// reaches zero, even if the stacks are not empty, which keeps the two ends
+    /// from passing each other and lets a range end before the tree does.
+    remaining: usize,
 }
 
 /// Methods for BstIter, parameterized over lifetime and element type of the BST
-impl<'a, E> BstIter<'a, E> {
+impl<'a, E, A> BstIter<'a, E, A> {
     /// Modifies the current iterator to add [node] and all its
     /// (recursive) left children
-    fn fill_left(&mut self, node: &'a Bst<E>) {
-        self.nodes.push(node);
-        if let Some(left_child) = node.left.as_ref() {
-            self.fill_left(left_child)
+    fn fill_left(&mut self, node: &'a Bst<E, A>) {
+        let mut node = Some(node);
+        while let Some(current) = node {
+            self.nodes.push(current);
+            node = current.left.as_deref();
+        }
+    }
+
+    /// Modifies the current iterator to add [node] and all its
+    /// (recursive) right children to the back stack
+    fn fill_right(&mut self, node: &'a Bst<E, A>) {
+        let mut node = Some(node);
+        while let Some(current) = node {
+            self.back.push(current);
+            node = current.right.as_deref();
         }
     }
 
     /// Creates a new iterator pointing to the leftmost (least)
     /// child of [node]
-    pub fn new(node: &'a Bst<E>) -> BstIter<'a, E> {
-        let mut this = Self { nodes: vec![] };
-        this.fill_left(node);
+    pub fn new(node: &'a Bst<E, A>) -> BstIter<'a, E, A> {
+        Self::from_root(Some(node))
+    }
+
+    /// Creates a new iterator over a possibly empty tree, pointing to
+    /// the leftmost (least) child of [root] if there is one
+    pub(crate) fn from_root(root: Option<&'a Bst<E, A>>) -> BstIter<'a, E, A> {
+        let mut this = Self {
+            nodes: vec![],
+            back: vec![],
+            remaining: root.map_or(0, |root| root.size),
+        };
+        if let Some(root) = root {
+            this.fill_left(root);
+            this.fill_right(root);
+        }
+        this
+    }
+
+    /// Creates a new iterator over the values of the tree at [root] that lie
+    /// within [range], using [cmp] to compare a bound with a value. The stacks are
+    /// seeded with only the paths to the first and last values in range, and the number of
+    /// values in range is counted from subtree sizes, so no comparisons are made
+    /// once iteration starts.
+    pub(crate) fn from_range<Q, R, F>(
+        root: Option<&'a Bst<E, A>>,
+        range: &R,
+        mut cmp: F,
+    ) -> BstIter<'a, E, A>
+    where
+        Q: ?Sized,
+        R: ops::RangeBounds<Q>,
+        F: FnMut(&Q, &E) -> Ordering,
+    {
+        let mut this = Self {
+            nodes: vec![],
+            back: vec![],
+            remaining: 0,
+        };
+        let mut node = root;
+        let mut skipped = 0;
+        while let Some(current) = node {
+            if before_range(range, &mut cmp, &current.value) {
+                skipped += Bst::size_of(&current.left) + 1;
+                node = current.right.as_deref();
+            } else {
+                this.nodes.push(current);
+                node = current.left.as_deref();
+            }
+        }
+
+        let mut node = root;
+        let mut through_end = 0;
+        while let Some(current) = node {
+            if !after_range(range, &mut cmp, &current.value) {
+                through_end += Bst::size_of(&current.left) + 1;
+                this.back.push(current);
+                node = current.right.as_deref();
+            } else {
+                node = current.left.as_deref();
+            }
+        }
+        this.remaining = through_end.saturating_sub(skipped);
         this
     }
 }
 
 /// Implements the built-in Iterator trait for BstIter.
 /// Allows use of BstIter in, e.g. for loops
-impl<'a, E> Iterator for BstIter<'a, E> {
+impl<'a, E, A> Iterator for BstIter<'a, E, A> {
     /// Item type of a BST iterator is a reference to the current
     /// node's value
     type Item = &'a E;
@@ -69,6 +303,10 @@ impl<'a, E> Iterator for BstIter<'a, E> {
     /// leftmost child of the current node's right child, or, if no
     /// right child exists, the previous node in the stack.
     fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
         let ret = self.nodes.pop();
         if let Some(node) = ret.as_ref() {
             if let Some(right_child) = node.right.as_ref() {
@@ -78,59 +316,1741 @@ impl<'a, E> Iterator for BstIter<'a, E> {
 
         ret.map(|ret| &ret.value)
     }
+
+    /// The number of values left is always known exactly
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// Allows a BstIter to be consumed from the greatest value downwards, e.g.
+/// with rev(), independently of (and without overlapping) the forward end.
+impl<'a, E, A> DoubleEndedIterator for BstIter<'a, E, A> {
+    /// Returns the current back node value (if present), and updates the
+    /// iterator to the previous node: the rightmost child of the current
+    /// node's left child, or, if no left child exists, the previous node
+    /// in the back stack.
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let ret = self.back.pop();
+        if let Some(node) = ret.as_ref() {
+            if let Some(left_child) = node.left.as_ref() {
+                self.fill_right(left_child);
+            }
+        }
+
+        ret.map(|ret| &ret.value)
+    }
+}
+
+impl<'a, E, A> ExactSizeIterator for BstIter<'a, E, A> {}
+
+/// Mutable counterpart of BstIter. Yields mutable references to the values of a
+/// BST in order, so it is only exposed through wrappers (such as the values of a
+/// map) that cannot change the ordering of the tree.
+#[derive(Debug)]
+pub(crate) struct BstIterMut<'a, E> {
+    /// Stack of values (each paired with the right subtree of its node) whose
+    /// left subtrees have not yet been fully visited. The value at the top of the
+    /// stack is the current one.
+    nodes: Vec<(&'a mut E, Option<&'a mut Bst<E>>)>,
+    /// Number of values left to yield
+    remaining: usize,
+}
+
+/// Methods for BstIterMut, parameterized over lifetime and element type of the BST
+impl<'a, E> BstIterMut<'a, E> {
+    /// Modifies the current iterator to add [node] and all its
+    /// left children, splitting each node into its value and right subtree
+    fn fill_left(&mut self, mut node: Option<&'a mut Bst<E>>) {
+        while let Some(current) = node {
+            let Bst {
+                value, left, right, ..
+            } = current;
+            self.nodes.push((value, right.as_deref_mut()));
+            node = left.as_deref_mut();
+        }
+    }
+
+    /// Creates a new iterator over a possibly empty tree, pointing to
+    /// the leftmost (least) child of [root] if there is one
+    pub(crate) fn from_root(root: Option<&'a mut Bst<E>>) -> BstIterMut<'a, E> {
+        let mut this = Self {
+            nodes: vec![],
+            remaining: root.as_ref().map_or(0, |root| root.size),
+        };
+        this.fill_left(root);
+        this
+    }
+}
+
+impl<'a, E> Iterator for BstIterMut<'a, E> {
+    type Item = &'a mut E;
+
+    /// Returns the current value and moves on to the leftmost node of its
+    /// right subtree, or else to the previous node in the stack
+    fn next(&mut self) -> Option<Self::Item> {
+        let (value, right) = self.nodes.pop()?;
+        self.fill_left(right);
+        self.remaining -= 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, E> ExactSizeIterator for BstIterMut<'a, E> {}
+
+/// Owning iterator for a BST, which moves the values out of the tree in order.
+/// Works like BstIter, except that the stack owns its nodes: each node's left
+/// child is detached as it is pushed and its right child as it is popped, so
+/// every node is freed as soon as its value has been yielded.
+#[derive(Debug)]
+pub struct IntoIter<E, A = ()> {
+    /// Stack of nodes that have the current node in their (detached) left
+    /// subtree. Current node is the top of the stack.
+    nodes: Vec<Box<Bst<E, A>>>,
+    /// Number of values left to yield
+    remaining: usize,
+}
+
+/// Methods for IntoIter, parameterized over element type of the BST
+impl<E, A> IntoIter<E, A> {
+    /// Modifies the current iterator to add [node] and all its
+    /// (recursive) left children, detaching each from its parent
+    fn fill_left(&mut self, mut node: Option<Box<Bst<E, A>>>) {
+        while let Some(mut current) = node {
+            node = current.left.take();
+            self.nodes.push(current);
+        }
+    }
+
+    /// Creates a new iterator that takes ownership of the possibly empty
+    /// tree at [root], pointing to its leftmost (least) child
+    pub(crate) fn from_root(root: Option<Box<Bst<E, A>>>) -> IntoIter<E, A> {
+        let mut this = Self {
+            nodes: vec![],
+            remaining: Bst::size_of(&root),
+        };
+        this.fill_left(root);
+        this
+    }
+}
+
+impl<E, A> Iterator for IntoIter<E, A> {
+    type Item = E;
+
+    /// Pops the current node, moves its right subtree onto the stack as for
+    /// BstIter, and returns the node's value, dropping the now childless node.
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut node = self.nodes.pop()?;
+        self.fill_left(node.right.take());
+        self.remaining -= 1;
+        Some(node.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<E, A> ExactSizeIterator for IntoIter<E, A> {}
+
+/// Consumes a BST, yielding its values in order
+impl<E, A> IntoIterator for Bst<E, A> {
+    type Item = E;
+    type IntoIter = IntoIter<E, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::from_root(Some(Box::new(self)))
+    }
 }
 
-/// Methods for Bst, parameterized over its element type (which must be comparable).
+/// Allows a borrowed BST to be used in, e.g. for loops
+impl<'a, E, A> IntoIterator for &'a Bst<E, A> {
+    type Item = &'a E;
+    type IntoIter = BstIter<'a, E, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        BstIter::new(self)
+    }
+}
+
+/// Structural helpers for Bst that do not depend on the element ordering.
+/// These operate on links (optional boxed subtrees) so that a subtree can be
+/// removed entirely by setting its link to None. The helpers here only read
+/// the tree (or its values in place), so need nothing of its aggregate.
+impl<E, A> Bst<E, A> {
+    /// Height of the (possibly empty) subtree at [link]
+    pub(crate) fn height_of(link: &Option<Box<Bst<E, A>>>) -> usize {
+        link.as_ref().map_or(0, |node| node.height)
+    }
+
+    /// Number of values in the (possibly empty) subtree at [link]
+    pub(crate) fn size_of(link: &Option<Box<Bst<E, A>>>) -> usize {
+        link.as_ref().map_or(0, |node| node.size)
+    }
+
+    /// Finds the value in the (possibly empty) subtree at [node] that [locate]
+    /// reports as Equal, descending left on Less and right on Greater.
+    pub(crate) fn get_by<F>(node: Option<&Bst<E, A>>, mut locate: F) -> Option<&E>
+    where
+        F: FnMut(&E) -> Ordering,
+    {
+        count_descent();
+        let mut node = node;
+        while let Some(current) = node {
+            match locate(&current.value) {
+                Ordering::Less => node = current.left.as_deref(),
+                Ordering::Greater => node = current.right.as_deref(),
+                Ordering::Equal => return Some(&current.value),
+            }
+        }
+        None
+    }
+
+    /// Returns the greatest value in the subtree at [node] that precedes the
+    /// key described by [locate] (which reports Greater for such values), or that
+    /// matches it (Equal) if [inclusive].
+    pub(crate) fn floor_by<F>(
+        node: Option<&Bst<E, A>>,
+        mut locate: F,
+        inclusive: bool,
+    ) -> Option<&E>
+    where
+        F: FnMut(&E) -> Ordering,
+    {
+        count_descent();
+        let mut node = node;
+        let mut found = None;
+        while let Some(current) = node {
+            match locate(&current.value) {
+                Ordering::Equal if inclusive => return Some(&current.value),
+                Ordering::Greater => {
+                    found = Some(&current.value);
+                    node = current.right.as_deref();
+                }
+                _ => node = current.left.as_deref(),
+            }
+        }
+        found
+    }
+
+    /// Returns the least value in the subtree at [node] that follows the key
+    /// described by [locate] (which reports Less for such values), or that
+    /// matches it (Equal) if [inclusive].
+    pub(crate) fn ceiling_by<F>(
+        node: Option<&Bst<E, A>>,
+        mut locate: F,
+        inclusive: bool,
+    ) -> Option<&E>
+    where
+        F: FnMut(&E) -> Ordering,
+    {
+        count_descent();
+        let mut node = node;
+        let mut found = None;
+        while let Some(current) = node {
+            match locate(&current.value) {
+                Ordering::Equal if inclusive => return Some(&current.value),
+                Ordering::Less => {
+                    found = Some(&current.value);
+                    node = current.left.as_deref();
+                }
+                _ => node = current.right.as_deref(),
+            }
+        }
+        found
+    }
+
+    /// Mutable counterpart of get_by. Callers must not change the value in a
+    /// way that affects its ordering, nor at all in a tree that caches an
+    /// aggregate (see modify_at).
+    pub(crate) fn get_mut_by<F>(node: Option<&mut Bst<E, A>>, mut locate: F) -> Option<&mut E>
+    where
+        F: FnMut(&E) -> Ordering,
+    {
+        count_descent();
+        let mut node = node;
+        while let Some(current) = node {
+            match locate(&current.value) {
+                Ordering::Less => node = current.left.as_deref_mut(),
+                Ordering::Greater => node = current.right.as_deref_mut(),
+                Ordering::Equal => return Some(&mut current.value),
+            }
+        }
+        None
+    }
+
+    /// Mutable counterpart of rank_by: returns the value that [locate] reports
+    /// as Equal if there is one, or else the number of values that precede the
+    /// key it describes (the in-order index a value for that key would have if
+    /// inserted). The same rules apply to the value as for get_mut_by.
+    pub(crate) fn get_mut_or_rank_by<F>(
+        node: Option<&mut Bst<E, A>>,
+        mut locate: F,
+    ) -> Result<&mut E, usize>
+    where
+        F: FnMut(&E) -> Ordering,
+    {
+        count_descent();
+        let mut node = node;
+        let mut rank = 0;
+        while let Some(current) = node {
+            match locate(&current.value) {
+                Ordering::Less => node = current.left.as_deref_mut(),
+                Ordering::Greater => {
+                    rank += Self::size_of(&current.left) + 1;
+                    node = current.right.as_deref_mut();
+                }
+                Ordering::Equal => return Ok(&mut current.value),
+            }
+        }
+        Err(rank)
+    }
+
+    /// Finds the value that [locate] reports as Equal, as for get_by, and also
+    /// counts the values that precede it. Returns that count (the in-order index
+    /// the value has, or would have if inserted) and whether the value was found.
+    pub(crate) fn rank_by<F>(node: Option<&Bst<E, A>>, mut locate: F) -> (usize, bool)
+    where
+        F: FnMut(&E) -> Ordering,
+    {
+        count_descent();
+        let mut node = node;
+        let mut rank = 0;
+        while let Some(current) = node {
+            match locate(&current.value) {
+                Ordering::Less => node = current.left.as_deref(),
+                Ordering::Greater => {
+                    rank += Self::size_of(&current.left) + 1;
+                    node = current.right.as_deref();
+                }
+                Ordering::Equal => return (rank + Self::size_of(&current.left), true),
+            }
+        }
+        (rank, false)
+    }
+
+    /// Returns the value with in-order index [index] in the subtree at [node],
+    /// descending by subtree sizes alone.
+    pub(crate) fn get_at(node: Option<&Bst<E, A>>, index: usize) -> Option<&E> {
+        count_descent();
+        let mut node = node;
+        let mut index = index;
+        while let Some(current) = node {
+            let left_size = Self::size_of(&current.left);
+            match index.cmp(&left_size) {
+                Ordering::Less => node = current.left.as_deref(),
+                Ordering::Equal => return Some(&current.value),
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    node = current.right.as_deref();
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns a locate function, as taken by take_by, detach_path and
+    /// Balance::take, that finds the value with in-order index [index] in the
+    /// tree, descending by subtree sizes alone, so it still works once the value
+    /// itself is out of order. It must be shown the nodes of a single descent
+    /// from the root, in turn.
+    pub(crate) fn locate_at(index: usize) -> impl FnMut(&Bst<E, A>) -> Ordering {
+        let mut index = index;
+        move |node| {
+            let left_size = Self::size_of(&node.left);
+            let order = index.cmp(&left_size);
+            if order == Ordering::Greater {
+                index -= left_size + 1;
+            }
+            order
+        }
+    }
+
+    /// Returns a locate function, as taken by insert_by and Balance::insert, that
+    /// places a new value so that it has in-order index [index] in the tree,
+    /// descending by subtree sizes alone. It must be shown the nodes of a single
+    /// descent from the root, in turn, and never reports Equal.
+    pub(crate) fn locate_insert_at(index: usize) -> impl FnMut(&E, &Bst<E, A>) -> Ordering {
+        // The value now at [index] is to follow the new one
+        let mut locate = Self::locate_at(index);
+        move |_, node| match locate(node) {
+            Ordering::Equal => Ordering::Less,
+            order => order,
+        }
+    }
+
+    /// Returns a mutable reference to the value with in-order index [index] in
+    /// the subtree at [node], descending by subtree sizes alone. Callers must not
+    /// change the value in a way that affects its ordering, nor at all in a tree
+    /// that caches an aggregate (see modify_at).
+    pub(crate) fn get_mut_at(node: Option<&mut Bst<E, A>>, index: usize) -> Option<&mut E> {
+        count_descent();
+        let mut node = node;
+        let mut index = index;
+        while let Some(current) = node {
+            let left_size = Self::size_of(&current.left);
+            match index.cmp(&left_size) {
+                Ordering::Less => node = current.left.as_deref_mut(),
+                Ordering::Equal => return Some(&mut current.value),
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    node = current.right.as_deref_mut();
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the least value of the (possibly empty) subtree at [node]
+    pub(crate) fn first_of(node: Option<&Bst<E, A>>) -> Option<&E> {
+        count_descent();
+        let mut node = node?;
+        while let Some(left) = node.left.as_deref() {
+            node = left;
+        }
+        Some(&node.value)
+    }
+
+    /// Returns the greatest value of the (possibly empty) subtree at [node]
+    pub(crate) fn last_of(node: Option<&Bst<E, A>>) -> Option<&E> {
+        count_descent();
+        let mut node = node?;
+        while let Some(right) = node.right.as_deref() {
+            node = right;
+        }
+        Some(&node.value)
+    }
+}
+
+/// Structural helpers for Bst that change the shape of the tree, and so must
+/// keep the aggregate of every node they touch up to date.
+impl<E, A: Monoid<E>> Bst<E, A> {
+    /// Makes a new node with the given value and subtrees, computing its height,
+    /// size and aggregate from theirs.
+    pub(crate) fn with_children(
+        value: E,
+        left: Option<Box<Bst<E, A>>>,
+        right: Option<Box<Bst<E, A>>>,
+    ) -> Self {
+        let mut node = Self {
+            value,
+            left: Link(left),
+            right: Link(right),
+            height: 0,
+            size: 0,
+            red: false,
+            aggregate: A::identity(),
+        };
+        node.update();
+        node
+    }
+
+    /// Builds a tree of the first [len] values yielded by [values], which must be
+    /// in order, splitting them evenly at every node so that every level of the
+    /// tree is full except possibly the last. That tree is balanced in every
+    /// mode: it is an AVL tree, and colouring the nodes of an incomplete last
+    /// level red (as is done here) makes it a red-black tree. Runs in O(len).
+    pub(crate) fn build_balanced<I>(values: &mut I, len: usize) -> Option<Box<Bst<E, A>>>
+    where
+        I: Iterator<Item = E>,
+    {
+        // Depth of the first level that is not full, given 2^d - 1 nodes above it
+        let red_depth = (len + 1).ilog2() as usize;
+        Self::build_subtree(values, len, 0, red_depth)
+    }
+
+    /// Builds the subtree of [len] values at [depth] for build_balanced
+    fn build_subtree<I>(
+        values: &mut I,
+        len: usize,
+        depth: usize,
+        red_depth: usize,
+    ) -> Option<Box<Bst<E, A>>>
+    where
+        I: Iterator<Item = E>,
+    {
+        if len == 0 {
+            return None;
+        }
+        let left_len = (len - 1) / 2;
+        let left = Self::build_subtree(values, left_len, depth + 1, red_depth);
+        let value = values.next().expect("enough values to build from");
+        let right = Self::build_subtree(values, len - 1 - left_len, depth + 1, red_depth);
+        let mut node = Self::with_children(value, left, right);
+        node.red = depth == red_depth;
+        Some(Box::new(node))
+    }
+
+    /// Aggregate of the values in the (possibly empty) subtree at [link]
+    pub(crate) fn aggregate_of(link: &Option<Box<Bst<E, A>>>) -> A {
+        link.as_ref()
+            .map_or_else(A::identity, |node| node.aggregate.clone())
+    }
+
+    /// Recomputes the height, size and aggregate of this node from those of its
+    /// children
+    pub(crate) fn update(&mut self) {
+        self.height = 1 + cmp::max(Self::height_of(&self.left), Self::height_of(&self.right));
+        self.size = 1 + Self::size_of(&self.left) + Self::size_of(&self.right);
+        let own = A::of(&self.value);
+        let with_left = match self.left.as_ref() {
+            Some(left) => A::combine(&left.aggregate, &own),
+            None => own,
+        };
+        self.aggregate = match self.right.as_ref() {
+            Some(right) => A::combine(&with_left, &right.aggregate),
+            None => with_left,
+        };
+    }
+
+    /// Recomputes the aggregate of this node after a value in its subtree was
+    /// changed in place, which leaves its height and size as they were. An
+    /// aggregate of a zero-sized type, such as (), has only one possible value,
+    /// so is never recomputed.
+    pub(crate) fn update_aggregate(&mut self) {
+        if mem::size_of::<A>() != 0 {
+            self.update();
+        }
+    }
+
+    /// Rotates [node] right, making its left child the new subtree root.
+    /// Heights and sizes are updated; colors are left to the caller.
+    pub(crate) fn rotate_right(mut node: Box<Bst<E, A>>) -> Box<Bst<E, A>> {
+        let mut pivot = node.left.take().expect("rotated node has a left child");
+        *node.left = pivot.right.take();
+        node.update();
+        *pivot.right = Some(node);
+        pivot.update();
+        pivot
+    }
+
+    /// Rotates [node] left, making its right child the new subtree root.
+    /// Heights and sizes are updated; colors are left to the caller.
+    pub(crate) fn rotate_left(mut node: Box<Bst<E, A>>) -> Box<Bst<E, A>> {
+        let mut pivot = node.right.take().expect("rotated node has a right child");
+        *node.right = pivot.left.take();
+        node.update();
+        *pivot.left = Some(node);
+        pivot.update();
+        pivot
+    }
+
+    /// Descends from [link] as directed by [locate], which is shown each node in
+    /// turn and reports Less to go left, Greater to go right or Equal to stop.
+    /// The nodes passed on the way are detached and returned top-down, each with
+    /// the direction taken from it, leaving at [link] the subtree where the
+    /// descent stopped (None if it ran off the tree). That subtree may then be
+    /// changed freely before attach_path puts the path back. This lets a change
+    /// deep in a degenerate tree be made without recursion, while still visiting
+    /// every node above it bottom-up afterwards.
+    pub(crate) fn detach_path<F>(
+        link: &mut Option<Box<Bst<E, A>>>,
+        mut locate: F,
+    ) -> Vec<(Box<Bst<E, A>>, Ordering)>
+    where
+        F: FnMut(&Bst<E, A>) -> Ordering,
+    {
+        count_descent();
+        let mut path = vec![];
+        while let Some(mut node) = link.take() {
+            let order = locate(&node);
+            *link = match order {
+                Ordering::Less => node.left.take(),
+                Ordering::Greater => node.right.take(),
+                Ordering::Equal => {
+                    *link = Some(node);
+                    break;
+                }
+            };
+            path.push((node, order));
+        }
+        path
+    }
+
+    /// Puts back the [path] detached from above [link] by detach_path, bottom-up,
+    /// updating each node of it for the subtree now below it.
+    pub(crate) fn attach_path(
+        link: &mut Option<Box<Bst<E, A>>>,
+        path: Vec<(Box<Bst<E, A>>, Ordering)>,
+    ) {
+        for (mut node, order) in path.into_iter().rev() {
+            match order {
+                Ordering::Less => *node.left = link.take(),
+                _ => *node.right = link.take(),
+            }
+            node.update();
+            *link = Some(node);
+        }
+    }
+
+    /// Inserts [new_val] into the subtree at [link] without rebalancing, where
+    /// [locate] directs, or merges it into an equal value with [merge], as for
+    /// Balance::insert. Returns None if inserted, or else the result of [merge].
+    pub(crate) fn insert_by<F, M, R>(
+        link: &mut Option<Box<Bst<E, A>>>,
+        new_val: E,
+        mut locate: F,
+        merge: M,
+    ) -> Option<R>
+    where
+        F: FnMut(&E, &Bst<E, A>) -> Ordering,
+        M: FnOnce(&mut E, E) -> R,
+    {
+        let path = Self::detach_path(link, |node| locate(&new_val, node));
+        let merged = match link.as_mut() {
+            Some(node) => {
+                let merged = merge(&mut node.value, new_val);
+                node.update_aggregate();
+                Some(merged)
+            }
+            None => {
+                *link = Some(Box::new(Bst::with_children(new_val, None, None)));
+                None
+            }
+        };
+        Self::attach_path(link, path);
+        merged
+    }
+
+    /// Removes the least node of the subtree at [link], splicing its right
+    /// child into its place. Returns the removed value, or None if [link] is empty.
+    pub(crate) fn take_first(link: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+        let path = Self::detach_path(link, |node| match node.left.as_ref() {
+            Some(_) => Ordering::Less,
+            None => Ordering::Equal,
+        });
+        let taken = link.take().map(|node| {
+            let Bst { value, right, .. } = *node;
+            *link = right.into_inner();
+            value
+        });
+        Self::attach_path(link, path);
+        taken
+    }
+
+    /// Removes the greatest node of the subtree at [link], splicing its left
+    /// child into its place. Returns the removed value, or None if [link] is empty.
+    pub(crate) fn take_last(link: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+        let path = Self::detach_path(link, |node| match node.right.as_ref() {
+            Some(_) => Ordering::Greater,
+            None => Ordering::Equal,
+        });
+        let taken = link.take().map(|node| {
+            let Bst { value, left, .. } = *node;
+            *link = left.into_inner();
+            value
+        });
+        Self::attach_path(link, path);
+        taken
+    }
+
+    /// Removes the node of the subtree at [link] that [locate] reports as Equal,
+    /// descending left on Less and right on Greater. A node with two children is
+    /// replaced by its in-order successor. Returns the removed value, or None if
+    /// no such node exists.
+    pub(crate) fn take_by<F>(link: &mut Option<Box<Bst<E, A>>>, locate: F) -> Option<E>
+    where
+        F: FnMut(&Bst<E, A>) -> Ordering,
+    {
+        let path = Self::detach_path(link, locate);
+        let taken = link.take().map(|node| {
+            let (value, replacement) = (*node).unlink();
+            *link = replacement;
+            value
+        });
+        Self::attach_path(link, path);
+        taken
+    }
+
+    /// Detaches this node from its children, returning its value and the subtree
+    /// that should take its place: the only child if there is at most one, or
+    /// the original children under the in-order successor otherwise.
+    fn unlink(self) -> (E, Option<Box<Bst<E, A>>>) {
+        let Bst {
+            value, left, right, ..
+        } = self;
+        match (left.into_inner(), right.into_inner()) {
+            (None, right) => (value, right),
+            (left, None) => (value, left),
+            (left, mut right) => {
+                let successor = Self::take_first(&mut right).expect("right subtree is not empty");
+                let replacement = Bst::with_children(successor, left, right);
+                (value, Some(Box::new(replacement)))
+            }
+        }
+    }
+
+    /// Applies [f] to the value with in-order index [index] in the subtree at
+    /// [link], descending by subtree sizes alone, and updates the aggregates of
+    /// that node and those above it. Returns the result of [f], or None if there
+    /// is no such value. [f] must not change the value in a way that affects its
+    /// ordering.
+    pub(crate) fn modify_at<F, R>(
+        link: &mut Option<Box<Bst<E, A>>>,
+        index: usize,
+        f: F,
+    ) -> Option<R>
+    where
+        F: FnOnce(&mut E) -> R,
+    {
+        let path = Self::detach_path(link, Self::locate_at(index));
+        let result = link.as_mut().map(|node| {
+            let result = f(&mut node.value);
+            node.update();
+            result
+        });
+        Self::attach_path(link, path);
+        result
+    }
+
+    /// Combines the aggregates of the values in the subtree at [node] that lie
+    /// within [range], using [cmp] to compare a bound with a value. Below the
+    /// highest node within the range, the paths to the two ends of the range are
+    /// followed, and each subtree that hangs inside the range from one of them
+    /// contributes its cached aggregate, so this takes O(height) time.
+    pub(crate) fn aggregate_range_by<Q, R, F>(node: Option<&Bst<E, A>>, range: &R, mut cmp: F) -> A
+    where
+        Q: ?Sized,
+        R: ops::RangeBounds<Q>,
+        F: FnMut(&Q, &E) -> Ordering,
+    {
+        let mut node = node;
+        let top = loop {
+            let current = match node {
+                Some(current) => current,
+                None => return A::identity(),
+            };
+            if before_range(range, &mut cmp, &current.value) {
+                node = current.right.as_deref();
+            } else if after_range(range, &mut cmp, &current.value) {
+                node = current.left.as_deref();
+            } else {
+                break current;
+            }
+        };
+        // Values from the start of the range up to top, gathered right to left
+        let mut lower = A::of(&top.value);
+        let mut node = top.left.as_deref();
+        while let Some(current) = node {
+            if before_range(range, &mut cmp, &current.value) {
+                node = current.right.as_deref();
+            } else {
+                let own = A::combine(&A::of(&current.value), &Self::aggregate_of(&current.right));
+                lower = A::combine(&own, &lower);
+                node = current.left.as_deref();
+            }
+        }
+        // Values after top up to the end of the range, gathered left to right
+        let mut upper = A::identity();
+        let mut node = top.right.as_deref();
+        while let Some(current) = node {
+            if after_range(range, &mut cmp, &current.value) {
+                node = current.left.as_deref();
+            } else {
+                let own = A::combine(&Self::aggregate_of(&current.left), &A::of(&current.value));
+                upper = A::combine(&upper, &own);
+                node = current.right.as_deref();
+            }
+        }
+        A::combine(&lower, &upper)
+    }
+
+    /// Converts the tree at [root] into one of the same shape (and colors) that
+    /// caches aggregates of type A2, computing them bottom-up in O(n) time. Nodes
+    /// are converted in post-order, as for clone, so that when a node is reached
+    /// the converted subtrees below it are on top of a stack.
+    pub(crate) fn reaggregate<A2: Monoid<E>>(
+        root: Option<Box<Bst<E, A>>>,
+    ) -> Option<Box<Bst<E, A2>>> {
+        let mut pending = vec![(root, false)];
+        let mut converted = vec![];
+        while let Some((link, children_converted)) = pending.pop() {
+            let mut node = match link {
+                Some(node) => node,
+                None => {
+                    converted.push(None);
+                    continue;
+                }
+            };
+            if !children_converted {
+                let (left, right) = (node.left.take(), node.right.take());
+                pending.push((Some(node), true));
+                pending.push((right, false));
+                pending.push((left, false));
+                continue;
+            }
+            let right = converted.pop().expect("right subtree was converted");
+            let left = converted.pop().expect("left subtree was converted");
+            let mut copy = Bst::with_children(node.value, left, right);
+            copy.red = node.red;
+            converted.push(Some(Box::new(copy)));
+        }
+        converted.pop().expect("root was converted")
+    }
+}
+
+/// Constructor for BSTs that cache no aggregate
 impl<E: cmp::Ord> Bst<E> {
     /// Convenience construction method for BST from fields.
     /// Should make a new Bst with the given value and empty left and right subtrees.
     pub fn new(value: E) -> Self {
-        Self {
-            value,
-            left: None,
-            right: None,
-        }
+        Self::with_children(value, None, None)
     }
+}
 
+/// Methods for Bst, parameterized over its element type (which must be
+/// comparable) and the aggregate cached in its nodes.
+impl<E: cmp::Ord, A: Monoid<E>> Bst<E, A> {
     /// Gets the iterator for this BST, starting at the least element.
-    pub fn iter(&self) -> BstIter<E> {
-        BstIter::new(&self)
+    pub fn iter(&self) -> BstIter<'_, E, A> {
+        BstIter::new(self)
+    }
+
+    /// Returns the number of values in the BST, which is kept as the tree changes.
+    /// A BST always holds at least its root value, so there is no is_empty.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the least value in the BST.
+    pub fn first(&self) -> &E {
+        Self::first_of(Some(self)).expect("a Bst is never empty")
+    }
+
+    /// Returns the greatest value in the BST.
+    pub fn last(&self) -> &E {
+        Self::last_of(Some(self)).expect("a Bst is never empty")
     }
 
     /// Inserts the value into the BST in the proper (sorted) position.
     /// Returns true if inserted, false if already present.
     pub fn insert(&mut self, new_val: E) -> bool {
-        match new_val.cmp(&self.value) {
-            Ordering::Equal => false,
-            Ordering::Less => match self.left.as_mut() {
-                Some(left_child) => left_child.insert(new_val),
-                None => {
-                    self.left = Some(Box::new(Bst::new(new_val)));
-                    true
+        let link = match new_val.cmp(&self.value) {
+            Ordering::Equal => return false,
+            Ordering::Less => &mut self.left,
+            Ordering::Greater => &mut self.right,
+        };
+        let inserted = Self::insert_by(
+            link,
+            new_val,
+            |new_val, node| new_val.cmp(&node.value),
+            |_, _| (),
+        )
+        .is_none();
+        if inserted {
+            self.update();
+        }
+        inserted
+    }
+
+    /// Returns a reference to the value in the BST equal to [key], if any.
+    /// The key may be any borrowed form of the element type, e.g. a &str
+    /// for a Bst<String>, but its ordering must match the element's.
+    pub fn get<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+    {
+        Self::get_by(Some(self), |v| key.cmp(v.borrow()))
+    }
+
+    /// Returns true if the BST contains a value equal to [key].
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Returns the greatest value in the BST less than or equal to [key], if any.
+    pub fn floor<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+    {
+        Self::floor_by(Some(self), |v| key.cmp(v.borrow()), true)
+    }
+
+    /// Returns the number of values in the BST less than [key], whether or not
+    /// [key] itself is present.
+    pub fn rank<Q>(&self, key: &Q) -> usize
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+    {
+        Self::rank_by(Some(self), |v| key.cmp(v.borrow())).0
+    }
+
+    /// Returns the [k]th least value in the BST, counting from 0, or None if
+    /// the BST has no more than [k] values.
+    pub fn select(&self, k: usize) -> Option<&E> {
+        Self::get_at(Some(self), k)
+    }
+
+    /// Returns the least value in the BST greater than or equal to [key], if any.
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+    {
+        Self::ceiling_by(Some(self), |v| key.cmp(v.borrow()), true)
+    }
+
+    /// Returns the greatest value in the BST strictly less than [key], if any.
+    pub fn predecessor<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+    {
+        Self::floor_by(Some(self), |v| key.cmp(v.borrow()), false)
+    }
+
+    /// Returns the least value in the BST strictly greater than [key], if any.
+    pub fn successor<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+    {
+        Self::ceiling_by(Some(self), |v| key.cmp(v.borrow()), false)
+    }
+
+    /// Gets an iterator over the values of the BST that lie within [range], in
+    /// order, e.g. `bst.range(1000..2000)`.
+    pub fn range<Q, R>(&self, range: R) -> BstIter<'_, E, A>
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+        R: ops::RangeBounds<Q>,
+    {
+        BstIter::from_range(Some(self), &range, |bound, v| bound.cmp(v.borrow()))
+    }
+
+    /// Removes the value equal to [key] from the BST, returning it if it was present.
+    /// Removing the root value splices in its in-order successor (or its only
+    /// child). A Bst always holds at least one value, so the value of a root
+    /// with no children cannot be removed; use a BstSet for trees that may
+    /// become empty.
+    pub fn take<Q>(&mut self, key: &Q) -> Option<E>
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+    {
+        let taken = match key.cmp(self.value.borrow()) {
+            Ordering::Less => Self::take_by(&mut self.left, |node| key.cmp(node.value.borrow())),
+            Ordering::Greater => {
+                Self::take_by(&mut self.right, |node| key.cmp(node.value.borrow()))
+            }
+            Ordering::Equal => match (self.left.take(), self.right.take()) {
+                (None, None) => return None,
+                (Some(child), None) | (None, Some(child)) => {
+                    return Some(mem::replace(self, *child).value)
                 }
-            },
-            Ordering::Greater => match self.right.as_mut() {
-                Some(right_child) => right_child.insert(new_val),
-                None => {
-                    self.right = Some(Box::new(Bst::new(new_val)));
-                    true
+                (left, mut right) => {
+                    let successor =
+                        Self::take_first(&mut right).expect("right subtree is not empty");
+                    *self.left = left;
+                    *self.right = right;
+                    Some(mem::replace(&mut self.value, successor))
                 }
             },
+        };
+        if taken.is_some() {
+            self.update();
         }
+        taken
+    }
+
+    /// Removes the value equal to [key] from the BST.
+    /// Returns true if removed, false if not present (or if it is the
+    /// value of a childless root, see [take]).
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+    {
+        self.take(key).is_some()
+    }
+
+    /// Returns the aggregate of all the values in the BST, which is cached at
+    /// its root, in O(1) time.
+    pub fn aggregate(&self) -> &A {
+        &self.aggregate
+    }
+
+    /// Returns the aggregate of the values of the BST that lie within [range],
+    /// e.g. `bst.aggregate_range(1000..2000)`, combined from the aggregates
+    /// cached along the paths to the ends of the range in O(height) time.
+    pub fn aggregate_range<Q, R>(&self, range: R) -> A
+    where
+        E: Borrow<Q>,
+        Q: cmp::Ord + ?Sized,
+        R: ops::RangeBounds<Q>,
+    {
+        Self::aggregate_range_by(Some(self), &range, |bound, v| bound.cmp(v.borrow()))
+    }
+
+    /// Converts the BST into one of the same shape whose nodes cache the
+    /// aggregate A2 instead, e.g. `Bst::new(1).with_aggregate::<Sum<i32>>()`,
+    /// computing it for every node in O(n) time.
+    pub fn with_aggregate<A2: Monoid<E>>(self) -> Bst<E, A2> {
+        *Bst::reaggregate(Some(Box::new(self))).expect("a Bst is never empty")
     }
 }
 
-/// Sum method for BST.
-/// Requires the ability to convert 0 to the element type and use the += operator
-/// with an element reference as the RHS.
-impl<'a, E: 'a + cmp::Ord + convert::From<i32> + ops::AddAssign<&'a E>> Bst<E> {
+/// Inserts each value in turn, as for insert
+impl<E: cmp::Ord, A: Monoid<E>> Extend<E> for Bst<E, A> {
+    fn extend<I: IntoIterator<Item = E>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+/// Folds over the elements of a BST, in order, built on its iterator.
+/// sum and product work for any type that the standard library can sum or
+/// multiply by reference, e.g. integers, floats and `Duration`; sum_with
+/// folds into anything else.
+impl<E: cmp::Ord, A: Monoid<E>> Bst<E, A> {
     /// Sums the elements of the tree.
-    /// Should use self.iter() to traverse the tree.
-    pub fn sum(&'a self) -> E {
-        let ret = E::from(0);
-        self.iter().fold(ret, |mut accum, value| {
-            accum.add_assign(value);
-            accum
-        })
+    pub fn sum<'a>(&'a self) -> E
+    where
+        E: iter::Sum<&'a E>,
+    {
+        self.iter().sum()
+    }
+
+    /// Multiplies the elements of the tree.
+    pub fn product<'a>(&'a self) -> E
+    where
+        E: iter::Product<&'a E>,
+    {
+        self.iter().product()
+    }
+
+    /// Folds the elements of the tree in order into [identity], applying
+    /// [combine] to the running total and each element in turn, e.g. to sum
+    /// values with no `Sum` impl or concatenate them into a `Vec`.
+    pub fn sum_with<T, F>(&self, identity: T, combine: F) -> T
+    where
+        F: FnMut(T, &E) -> T,
+    {
+        self.iter().fold(identity, combine)
+    }
+
+    /// Returns the element with the least key under [f]; of several, the
+    /// first in order.
+    pub fn min_by_key<K: cmp::Ord, F: FnMut(&E) -> K>(&self, mut f: F) -> &E {
+        self.iter()
+            .min_by_key(|value| f(value))
+            .expect("a Bst is never empty")
+    }
+
+    /// Returns the element with the greatest key under [f]; of several, the
+    /// last in order.
+    pub fn max_by_key<K: cmp::Ord, F: FnMut(&E) -> K>(&self, mut f: F) -> &E {
+        self.iter()
+            .max_by_key(|value| f(value))
+            .expect("a Bst is never empty")
+    }
+
+    /// Returns the arithmetic mean of the elements as f64, summed as exactly
+    /// as their type allows; see AsF64::mean_of.
+    pub fn mean(&self) -> f64
+    where
+        E: AsF64,
+    {
+        E::mean_of(self.iter())
+    }
+}
+
+/// A number that can be converted to f64 for taking a mean, as by `as f64`:
+/// to the nearest f64, so that integers wider than 52 bits may be rounded.
+/// Implemented for every primitive integer and float type.
+pub trait AsF64 {
+    /// This number as the nearest f64.
+    fn as_f64(&self) -> f64;
+
+    /// Returns the arithmetic mean of [values] as f64, or NaN if there are
+    /// none. By default each value is converted with as_f64 and the results
+    /// are summed with Neumaier's compensated summation, which keeps the
+    /// low-order bits that adding a small value to a large sum would round
+    /// away. Integers of up to 64 bits are instead summed exactly in an i128,
+    /// so only the final division rounds.
+    fn mean_of<'a, I>(values: I) -> f64
+    where
+        Self: 'a,
+        I: Iterator<Item = &'a Self>,
+    {
+        let (mut sum, mut compensation, mut len) = (0.0f64, 0.0f64, 0usize);
+        for value in values {
+            let value = value.as_f64();
+            let total = sum + value;
+            compensation += if sum.abs() >= value.abs() {
+                (sum - total) + value
+            } else {
+                (value - total) + sum
+            };
+            sum = total;
+            len += 1;
+        }
+        (sum + compensation) / len as f64
+    }
+}
+
+macro_rules! impl_as_f64 {
+    ($($number:ty),*) => {
+        $(
+            impl AsF64 for $number {
+                fn as_f64(&self) -> f64 {
+                    *self as f64
+                }
+            }
+        )*
+    };
+}
+
+/// As impl_as_f64, for integers narrow enough that any number of them can be
+/// summed exactly in an i128
+macro_rules! impl_as_f64_exact {
+    ($($number:ty),*) => {
+        $(
+            impl AsF64 for $number {
+                fn as_f64(&self) -> f64 {
+                    *self as f64
+                }
+
+                fn mean_of<'a, I>(values: I) -> f64
+                where
+                    I: Iterator<Item = &'a Self>,
+                {
+                    let (mut sum, mut len) = (0i128, 0i128);
+                    for &value in values {
+                        sum += value as i128;
+                        len += 1;
+                    }
+                    if len == 0 {
+                        return f64::NAN;
+                    }
+                    // Dividing first keeps a sum too wide for f64 from rounding
+                    // away the fraction
+                    (sum / len) as f64 + (sum % len) as f64 / len as f64
+                }
+            }
+        )*
+    };
+}
+
+impl_as_f64_exact!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+impl_as_f64!(i128, u128, f32, f64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Rng;
+
+    #[test]
+    fn take_splices_out_nodes() {
+        //         50
+        //     30      70
+        //   20  40  60  80
+        //      35     65
+        let mut tree = Bst::new(50);
+        for value in [30, 70, 20, 40, 60, 80, 35, 65] {
+            tree.insert(value);
+        }
+
+        // Two children: the in-order successor takes the node's place
+        assert_eq!(tree.take(&30), Some(30));
+        assert!(tree.iter().copied().eq([20, 35, 40, 50, 60, 65, 70, 80]));
+        // Leaf
+        assert_eq!(tree.take(&20), Some(20));
+        // One child, which is spliced into its parent
+        assert_eq!(tree.take(&60), Some(60));
+        assert!(tree.iter().copied().eq([35, 40, 50, 65, 70, 80]));
+        assert_eq!(tree.take(&60), None);
+        assert!(!tree.remove(&99));
+        assert_eq!(tree.len(), 6);
+
+        // Root with two children: replaced in place by its successor
+        assert_eq!(tree.take(&50), Some(50));
+        assert_eq!(tree.value, 65);
+        assert!(tree.iter().copied().eq([35, 40, 65, 70, 80]));
+        assert_eq!(tree.len(), 5);
+
+        // Root with one child: the child becomes the root
+        let mut tree = Bst::new(1);
+        tree.insert(2);
+        tree.insert(3);
+        assert_eq!(tree.take(&1), Some(1));
+        assert_eq!(tree.value, 2);
+        assert_eq!(tree.take(&2), Some(2));
+        assert!(tree.iter().copied().eq([3]));
+        // A childless root cannot be removed
+        assert_eq!(tree.take(&3), None);
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn contains_and_get_borrowed_keys() {
+        let mut tree = Bst::new("pear".to_string());
+        for word in ["apple", "fig", "quince"] {
+            tree.insert(word.to_string());
+        }
+        for word in ["apple", "fig", "pear", "quince"] {
+            assert!(tree.contains(word));
+            assert_eq!(tree.get(word).map(String::as_str), Some(word));
+        }
+        for word in ["", "banana", "figs", "zucchini"] {
+            assert!(!tree.contains(word));
+            assert_eq!(tree.get(word), None);
+        }
+        assert!(tree.contains(&"fig".to_string()));
+
+        let mut set = BstSet::new();
+        for word in ["lime", "kiwi", "mango"] {
+            set.insert(word.to_string());
+        }
+        assert!(set.contains("lime"));
+        assert_eq!(set.get("mango"), Some(&"mango".to_string()));
+        assert!(!set.contains("lemon"));
+        assert_eq!(set.get("melon"), None);
+        assert!(!BstSet::<String>::new().contains(""));
+
+        let mut numbers = BstSet::new();
+        for value in 0..100u32 {
+            numbers.insert(value * 37 % 100 * 2);
+        }
+        for key in 0..201 {
+            assert_eq!(numbers.contains(&key), key % 2 == 0 && key < 200);
+            assert_eq!(numbers.get(&key).is_some(), numbers.contains(&key));
+        }
+    }
+
+    #[test]
+    fn neighbour_lookups() {
+        let mut tree = Bst::new(40);
+        for value in [20, 60, 10, 30, 50, 70] {
+            tree.insert(value);
+        }
+        assert_eq!(tree.floor(&35), Some(&30));
+        assert_eq!(tree.floor(&30), Some(&30));
+        assert_eq!(tree.floor(&5), None);
+        assert_eq!(tree.floor(&99), Some(&70));
+        assert_eq!(tree.ceiling(&35), Some(&40));
+        assert_eq!(tree.ceiling(&40), Some(&40));
+        assert_eq!(tree.ceiling(&5), Some(&10));
+        assert_eq!(tree.ceiling(&99), None);
+        assert_eq!(tree.predecessor(&40), Some(&30));
+        assert_eq!(tree.predecessor(&45), Some(&40));
+        assert_eq!(tree.predecessor(&10), None);
+        assert_eq!(tree.predecessor(&99), Some(&70));
+        assert_eq!(tree.successor(&40), Some(&50));
+        assert_eq!(tree.successor(&45), Some(&50));
+        assert_eq!(tree.successor(&70), None);
+        assert_eq!(tree.successor(&5), Some(&10));
+
+        let values: Vec<i32> = (0..100).map(|v| v * 3).collect();
+        let mut set = AvlSet::new();
+        for &value in &values {
+            set.insert(value);
+        }
+        for key in -5..305 {
+            assert_eq!(set.floor(&key), values.iter().rev().find(|&&v| v <= key));
+            assert_eq!(set.ceiling(&key), values.iter().find(|&&v| v >= key));
+            assert_eq!(
+                set.predecessor(&key),
+                values.iter().rev().find(|&&v| v < key)
+            );
+            assert_eq!(set.successor(&key), values.iter().find(|&&v| v > key));
+        }
+        let empty = RbSet::<i32>::new();
+        assert_eq!(empty.floor(&0), None);
+        assert_eq!(empty.successor(&0), None);
+
+        let mut map: BstMap<&str, usize> = BstMap::new();
+        for (key, value) in [("b", 1), ("d", 2), ("f", 3)] {
+            map.insert(key, value);
+        }
+        assert_eq!(map.floor_key_value("c"), Some((&"b", &1)));
+        assert_eq!(map.floor_key_value("a"), None);
+        assert_eq!(map.ceiling_key_value("d"), Some((&"d", &2)));
+        assert_eq!(map.ceiling_key_value("g"), None);
+        assert_eq!(map.predecessor_key_value("d"), Some((&"b", &1)));
+        assert_eq!(map.predecessor_key_value("b"), None);
+        assert_eq!(map.successor_key_value("d"), Some((&"f", &3)));
+        assert_eq!(map.successor_key_value("f"), None);
+        assert_eq!(map.successor_key_value("a"), Some((&"b", &1)));
+    }
+
+    #[test]
+    fn range_bounds() {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+        use std::ops::RangeBounds;
+
+        let values: Vec<i32> = (0..20).map(|v| v * 2).collect();
+        let mut set = RbSet::new();
+        let mut tree = Bst::new(20);
+        for &value in &values {
+            set.insert(value);
+            tree.insert(value);
+        }
+        let bounds = |v: i32| [Included(v), Excluded(v), Unbounded];
+        for start in -2..42 {
+            for end in -2..42 {
+                for range in bounds(start)
+                    .into_iter()
+                    .flat_map(|s| bounds(end).map(|e| (s, e)))
+                {
+                    let expected: Vec<i32> = values
+                        .iter()
+                        .copied()
+                        .filter(|v| range.contains(v))
+                        .collect();
+                    let within = set.range(range);
+                    assert_eq!(within.len(), expected.len());
+                    assert!(within.copied().eq(expected.iter().copied()));
+                    assert!(tree.range(range).copied().eq(expected.iter().copied()));
+                }
+            }
+        }
+
+        assert!(set.range(10..10).next().is_none());
+        assert!(set.range(11..12).next().is_none());
+        assert!(set.range(10..=10).copied().eq([10]));
+        // A reversed range is empty rather than a panic
+        assert_eq!(set.range((Included(30), Excluded(10))).len(), 0);
+        assert!(set.range((Included(30), Included(10))).next().is_none());
+        assert!(set.range(100..).next().is_none());
+        assert!(set.range(..0).next().is_none());
+        assert!(RbSet::<i32>::new().range(..).next().is_none());
+
+        let mut map: AvlMap<String, usize> = AvlMap::new();
+        for word in ["apple", "banana", "cherry", "date"] {
+            map.insert(word.to_string(), word.len());
+        }
+        let keys: Vec<&str> = map
+            .range::<str, _>((Included("b"), Excluded("d")))
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(keys, ["banana", "cherry"]);
+        assert_eq!(map.range::<str, _>((Excluded("c"), Unbounded)).len(), 2);
+    }
+
+    /// Takes values from both ends of [iter] in an order chosen by [rng] until
+    /// it is exhausted, checking that the ends meet without crossing: the
+    /// values taken from the front, followed by those from the back reversed,
+    /// must be [expected].
+    fn check_both_ends<'a, I>(mut iter: I, expected: &[i32], rng: &mut Rng)
+    where
+        I: DoubleEndedIterator<Item = &'a i32> + ExactSizeIterator,
+    {
+        let (mut front, mut back) = (vec![], vec![]);
+        loop {
+            assert_eq!(iter.len(), expected.len() - front.len() - back.len());
+            let next = if rng.below(2) == 0 {
+                iter.next().map(|v| front.push(*v))
+            } else {
+                iter.next_back().map(|v| back.push(*v))
+            };
+            if next.is_none() {
+                break;
+            }
+        }
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+        front.extend(back.into_iter().rev());
+        assert_eq!(front, expected);
+    }
+
+    #[test]
+    fn ends_meet_without_crossing() {
+        let mut rng = Rng(0xd0e5);
+        for len in 0..40 {
+            let values: Vec<i32> = (0..len).collect();
+            let mut set = AvlSet::new();
+            let mut chain = BstSet::new();
+            for &value in &values {
+                set.insert(value);
+                chain.insert(value);
+            }
+            for _ in 0..10 {
+                check_both_ends(set.iter(), &values, &mut rng);
+                check_both_ends(chain.iter(), &values, &mut rng);
+                let (start, end) = (len / 4, len - len / 4);
+                let within = &values[start as usize..end as usize];
+                check_both_ends(set.range(start..end), within, &mut rng);
+                check_both_ends(chain.range(start..end), within, &mut rng);
+            }
+        }
+
+        let mut tree = Bst::new(3);
+        for _ in 0..10 {
+            check_both_ends(tree.iter(), &[3], &mut rng);
+        }
+        for value in [1, 5, 0, 2, 4, 6] {
+            tree.insert(value);
+        }
+        for _ in 0..20 {
+            check_both_ends(tree.iter(), &[0, 1, 2, 3, 4, 5, 6], &mut rng);
+        }
+
+        let mut multiset = RbMultiset::new();
+        for value in [1, 3, 2, 3, 1, 3] {
+            multiset.insert(value);
+        }
+        for _ in 0..20 {
+            check_both_ends(multiset.iter(), &[1, 1, 2, 3, 3, 3], &mut rng);
+        }
+        let mut map = AvlMap::new();
+        for key in 0..9 {
+            map.insert(key, key);
+        }
+        let mut keys = map.keys();
+        assert_eq!(keys.next(), Some(&0));
+        assert_eq!(keys.next_back(), Some(&8));
+        assert_eq!(keys.len(), 7);
+        assert!(keys.rev().copied().eq((1..8).rev()));
+    }
+
+    #[test]
+    fn rank_and_select() {
+        let mut tree = Bst::new(50);
+        tree.extend([30, 70, 20, 40, 60, 80]);
+        assert_eq!(tree.rank(&50), 3);
+        assert_eq!(tree.rank(&45), 3);
+        assert_eq!(tree.rank(&0), 0);
+        assert_eq!(tree.rank(&99), 7);
+        assert_eq!(tree.select(0), Some(&20));
+        assert_eq!(tree.select(6), Some(&80));
+        assert_eq!(tree.select(7), None);
+        assert_eq!(tree.select(usize::MAX), None);
+        tree.remove(&50);
+        tree.remove(&20);
+        assert_eq!(tree.rank(&50), 2);
+        assert_eq!(tree.rank(&60), 2);
+        assert!((0..5)
+            .filter_map(|k| tree.select(k))
+            .eq([30, 40, 60, 70, 80].iter()));
+        assert_eq!(tree.select(5), None);
+
+        let mut set: AvlSet<i32> = (0..100).collect();
+        for value in (20..40).chain((50..100).step_by(3)) {
+            set.remove(&value);
+        }
+        let values: Vec<i32> = set.iter().copied().collect();
+        for key in -1..101 {
+            let rank = values.iter().filter(|&&v| v < key).count();
+            assert_eq!(set.rank(&key), rank);
+        }
+        for (k, value) in values.iter().enumerate() {
+            assert_eq!(set.select(k), Some(value));
+            assert_eq!(set.rank(value), k);
+        }
+        assert_eq!(set.select(values.len()), None);
+        assert_eq!(AvlSet::<i32>::new().select(0), None);
+        assert_eq!(AvlSet::<i32>::new().rank(&0), 0);
+
+        let mut map: RbMap<i32, char> = [(1, 'a'), (3, 'c'), (5, 'e')].into_iter().collect();
+        assert_eq!(map.rank(&4), 2);
+        assert_eq!(map.select(2), Some((&5, &'e')));
+        map.remove(&3);
+        assert_eq!(map.rank(&4), 1);
+        assert_eq!(map.select(1), Some((&5, &'e')));
+        assert_eq!(map.select(2), None);
+    }
+
+    #[test]
+    fn owning_and_borrowing_iteration() {
+        let mut tree = Bst::new(String::from("m"));
+        for value in ["d", "t", "a", "q"] {
+            tree.insert(value.to_string());
+        }
+        let mut borrowed = Vec::new();
+        for value in &tree {
+            borrowed.push(value.as_str());
+        }
+        assert_eq!(borrowed, ["a", "d", "m", "q", "t"]);
+        let mut owned = tree.into_iter();
+        assert_eq!(owned.len(), 5);
+        assert_eq!(owned.next().as_deref(), Some("a"));
+        assert_eq!(owned.next().as_deref(), Some("d"));
+        assert_eq!(owned.len(), 3);
+        drop(owned);
+
+        let mut set = RbSet::new();
+        for value in [5, 1, 4, 2, 3] {
+            set.insert(value);
+        }
+        assert_eq!((&set).into_iter().len(), 5);
+        assert!(set.into_iter().eq(1..=5));
+        assert!(RbSet::<i32>::new().into_iter().next().is_none());
+
+        let mut map = AvlMap::new();
+        for (key, value) in [(2, 'b'), (1, 'a'), (3, 'c')] {
+            map.insert(key, value);
+        }
+        let mut keys = 0;
+        for (key, _) in &map {
+            keys += key;
+        }
+        assert_eq!(keys, 6);
+        assert!(map.into_iter().eq([(1, 'a'), (2, 'b'), (3, 'c')]));
+
+        let mut multiset = RbMultiset::new();
+        for value in [2, 1, 2] {
+            multiset.insert(value);
+        }
+        let mut counted = Vec::new();
+        for value in &multiset {
+            counted.push(*value);
+        }
+        assert_eq!(counted, [1, 2, 2]);
+    }
+
+    /// Moves values of a set of B with update, checking against a BTreeSet
+    fn check_update<B: Balance>() {
+        use std::collections::BTreeSet;
+
+        let mut rng = Rng(0x0bda);
+        let mut set: TreeSet<u64, B> = (0..200).map(|v| v * 2).collect();
+        let mut expected: BTreeSet<u64> = set.iter().copied().collect();
+        for _ in 0..2_000 {
+            let (key, to) = (rng.below(400), rng.below(400));
+            let found = expected.remove(&key);
+            if found {
+                expected.insert(to);
+            }
+            assert_eq!(set.update(&key, |v| *v = to), found);
+            assert!(set.iter().eq(expected.iter()));
+        }
+        // Changing a value without moving it keeps it in place
+        let first = *set.first().expect("set is not empty");
+        assert!(set.update(&first, |v| *v = 0));
+        assert_eq!(set.first(), Some(&0));
+    }
+
+    #[test]
+    fn update_reorders_moved_values() {
+        check_update::<Unbalanced>();
+        check_update::<Avl>();
+        check_update::<RedBlack>();
+    }
+
+    /// Builds the tree that inserting 0..n in ascending order into a Bst produces:
+    /// a chain of right children, n deep. Inserting one value at a time would
+    /// take O(n^2) time, so the chain is linked up directly, from the bottom.
+    fn ascending_chain(n: u32) -> Bst<u32> {
+        let mut chain = Bst::new(n - 1);
+        for value in (0..n - 1).rev() {
+            chain = Bst::with_children(value, None, Some(Box::new(chain)));
+        }
+        chain
+    }
+
+    #[test]
+    fn degenerate_tree_does_not_overflow_stack() {
+        const N: u32 = 1_000_000;
+        let mut tree = ascending_chain(N - 1);
+        assert!(tree.insert(N - 1));
+        assert!(!tree.insert(N / 2));
+        assert_eq!(tree.len(), N as usize);
+        assert!(tree.iter().copied().eq(0..N));
+        assert!(tree.iter().rev().copied().eq((0..N).rev()));
+
+        let printed = tree.to_string();
+        assert!(printed.starts_with("0 1 2 "));
+        assert!(printed.ends_with(" 999998 999999"));
+        let debugged = format!("{:?}", tree);
+        assert!(debugged.starts_with("{0, 1, 2, "));
+        assert!(debugged.ends_with(", 999998, 999999}"));
+
+        let copy = tree.clone();
+        drop(tree);
+        assert!(copy.into_iter().eq(0..N));
+    }
+
+    #[test]
+    fn debug_formats_degenerate_trees() {
+        const N: u32 = 200_000;
+        let mut set = BstSet::new();
+        set.root = Some(Box::new(ascending_chain(N)));
+        let debugged = format!("{:?}", set);
+        assert!(debugged.starts_with("{0, 1, 2, "));
+        assert!(debugged.ends_with(", 199998, 199999}"));
+
+        let map: BstMap<u32, i64> = (0..3).map(|key| (key, -(key as i64))).collect();
+        assert_eq!(format!("{:?}", map), "{0: 0, 1: -1, 2: -2}");
+        assert_eq!(format!("{:?}", RbSet::<i32>::new()), "{}");
+        assert_eq!(format!("{:?}", Bst::new("root")), "{\"root\"}");
+    }
+
+    #[test]
+    fn ascending_inserts() {
+        let mut tree = Bst::new(0);
+        for value in 1..10_000 {
+            assert!(tree.insert(value));
+        }
+        assert!(tree.iter().copied().eq(0..10_000));
+    }
+
+    #[test]
+    fn integer_folds() {
+        let mut tree = Bst::new(3i32);
+        tree.extend([-4, 1, 5, 2]);
+        assert_eq!(tree.sum(), 7);
+        assert_eq!(tree.product(), -120);
+        assert_eq!(tree.sum_with(0, |total, value| total + value * value), 55);
+        assert_eq!(tree.min_by_key(|value| value.abs()), &1);
+        assert_eq!(tree.max_by_key(|value| value.abs()), &5);
+        assert_eq!(tree.max_by_key(|value| value % 2), &5);
+        assert_eq!(tree.mean(), 1.4);
+        assert_eq!(tree.iter().copied().sum::<i32>(), 7);
+
+        let small: AvlSet<u8> = (200..=255).collect();
+        assert_eq!(small.mean(), Some(227.5));
+        assert_eq!(
+            small.sum_with(0u32, |total, &value| total + value as u32),
+            12_740
+        );
+
+        let empty = RbSet::<i32>::new();
+        assert_eq!(empty.sum(), 0);
+        assert_eq!(empty.product(), 1);
+        assert_eq!(empty.min_by_key(|value| *value), None);
+        assert_eq!(empty.max_by_key(|value| *value), None);
+        assert_eq!(empty.mean(), None);
+
+        // Wide integers are rounded to f64 rather than excluded
+        let wide = Bst::new(i64::MAX);
+        assert_eq!(wide.mean(), 9.223_372_036_854_776e18);
+        let large: BstSet<u64> = [1 << 60, 3 << 60].into_iter().collect();
+        assert_eq!(large.mean(), Some((1u64 << 61) as f64));
+        let lengths: AvlSet<usize> = (1..=4).collect();
+        assert_eq!(lengths.mean(), Some(2.5));
+        // Integers of up to 64 bits are summed exactly, however far apart, and
+        // wider ones with compensation for rounding
+        let spread: BstSet<i64> = [i64::MIN + 1, 1, i64::MAX].into_iter().collect();
+        assert_eq!(spread.mean(), Some(1.0 / 3.0));
+        let mut signed = Bst::new(-(1i128 << 100));
+        signed.insert(1 << 100);
+        signed.insert(3);
+        assert_eq!(signed.mean(), 1.0);
+
+        let counts: AvlMultiset<u64> = [2, 2, 3].into_iter().collect();
+        assert_eq!(counts.sum(), 7);
+    }
+
+    /// A float that is totally ordered, as Bst requires
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Celsius(f64);
+
+    impl Eq for Celsius {}
+
+    impl PartialOrd for Celsius {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Celsius {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.0.total_cmp(&other.0)
+        }
+    }
+
+    impl<'a> iter::Sum<&'a Celsius> for Celsius {
+        fn sum<I: Iterator<Item = &'a Celsius>>(iter: I) -> Self {
+            Celsius(iter.map(|value| value.0).sum())
+        }
+    }
+
+    impl AsF64 for Celsius {
+        fn as_f64(&self) -> f64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn float_folds() {
+        let mut tree = Bst::new(Celsius(21.5));
+        tree.extend([Celsius(-3.0), Celsius(0.5), Celsius(18.0)]);
+        assert_eq!(tree.sum(), Celsius(37.0));
+        assert_eq!(tree.mean(), 9.25);
+        assert_eq!(tree.min_by_key(|value| value.0.abs() as i64), &Celsius(0.5));
+        assert_eq!(tree.max_by_key(|value| -value.0 as i64), &Celsius(-3.0));
+
+        let mut set = RbSet::with_comparator(|a: &f64, b: &f64| a.total_cmp(b));
+        set.extend([0.5, 4.0, -2.0, 1.5]);
+        assert_eq!(set.sum(), 4.0);
+        assert_eq!(set.product(), -6.0);
+        assert_eq!(set.mean(), Some(1.0));
+        assert_eq!(set.iter().copied().sum::<f64>(), 4.0);
+
+        // The small value is not lost to rounding beside the large ones
+        let mut spread = RbSet::with_comparator(|a: &f64, b: &f64| a.total_cmp(b));
+        spread.extend([1e100, 1.0, -1e100]);
+        assert_eq!(spread.mean(), Some(1.0 / 3.0));
+    }
+
+    /// A vector with no zero to start a sum from but its own
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    impl<'a> iter::Sum<&'a Point> for Point {
+        fn sum<I: Iterator<Item = &'a Point>>(iter: I) -> Self {
+            iter.fold(Point { x: 0, y: 0 }, |total, point| Point {
+                x: total.x + point.x,
+                y: total.y + point.y,
+            })
+        }
+    }
+
+    #[test]
+    fn custom_folds() {
+        use std::time::Duration;
+
+        let mut waits = Bst::new(Duration::from_millis(250));
+        waits.extend([Duration::from_secs(1), Duration::from_millis(750)]);
+        assert_eq!(waits.sum(), Duration::from_secs(2));
+        assert_eq!(
+            waits.iter().copied().sum::<Duration>(),
+            Duration::from_secs(2)
+        );
+
+        let points: BstSet<Point> = [
+            Point { x: 1, y: 2 },
+            Point { x: -3, y: 4 },
+            Point { x: 5, y: -6 },
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(points.sum(), Point { x: 3, y: 0 });
+        assert_eq!(
+            points.min_by_key(|point| point.y),
+            Some(&Point { x: 5, y: -6 })
+        );
+        assert_eq!(
+            points.max_by_key(|point| point.x * point.x + point.y * point.y),
+            Some(&Point { x: 5, y: -6 })
+        );
+        let xs = points.sum_with(Vec::new(), |mut xs, point| {
+            xs.push(point.x);
+            xs
+        });
+        assert_eq!(xs, [-3, 1, 5]);
+        assert_eq!(BstSet::<Point>::new().sum(), Point { x: 0, y: 0 });
     }
 }
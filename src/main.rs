@@ -1,4 +1,4 @@
-use bst_rs::Bst;
+use bst_rs::{AvlSet, Bst, BstSet};
 
 fn main() {
     // build sample tree containing 3, 5
@@ -16,4 +16,19 @@ fn main() {
         println!("{}", val);
     }
     println!("Testing sum\n{}", sample.sum());
+
+    // build a set starting from no elements
+    let mut set = BstSet::new();
+    println!("Testing empty set\n{} {}", set.len(), set.sum());
+    set.insert(5);
+    set.insert(3);
+    println!("{} {}", set.len(), set);
+
+    // sorted input stays balanced in an AVL set
+    let mut avl = AvlSet::new();
+    for val in 1..=7 {
+        avl.insert(val);
+    }
+    avl.remove(&4);
+    println!("Testing AVL set\n{} {}", avl, avl.sum());
 }
@@ -0,0 +1,1042 @@
+use std::borrow::Borrow;
+use std::cmp;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops;
+
+use crate::{
+    rb, Avl, Balance, Bst, BstIter, BstIterMut, Compare, IntoIter, Monoid, Natural, RedBlack,
+    Unbalanced,
+};
+
+/// An ordered map from keys of type K to values of type V, kept balanced by
+/// the strategy B. Entries are stored as (key, value) pairs in the same kind of
+/// tree that backs a TreeSet, ordered by key alone using the comparator C.
+/// Each node caches the aggregate A of the entries in its subtree (nothing, by
+/// default; see Monoid).
+#[derive(Clone)]
+pub struct TreeMap<K, V, B, C = Natural, A = ()> {
+    /// Root node of the tree, or None if the map is empty
+    pub(crate) root: Option<Box<Bst<(K, V), A>>>,
+    balance: PhantomData<B>,
+    /// Ordering of the keys
+    cmp: C,
+}
+
+/// A map backed by a plain, never-rebalanced binary search tree
+pub type BstMap<K, V, C = Natural, A = ()> = TreeMap<K, V, Unbalanced, C, A>;
+
+/// A map backed by an AVL tree
+pub type AvlMap<K, V, C = Natural, A = ()> = TreeMap<K, V, Avl, C, A>;
+
+/// A map backed by a red-black tree
+pub type RbMap<K, V, C = Natural, A = ()> = TreeMap<K, V, RedBlack, C, A>;
+
+/// An empty map is the default
+impl<K, V, B, C: Default, A: Monoid<(K, V)>> Default for TreeMap<K, V, B, C, A> {
+    fn default() -> Self {
+        Self::from_root(None, C::default())
+    }
+}
+
+/// Formats the map as its entries, in key order, e.g. `{1: "a", 2: "b"}`.
+/// The entries are visited with BstIter, so this cannot overflow the stack.
+impl<K: fmt::Debug, V: fmt::Debug, B, C, A> fmt::Debug for TreeMap<K, V, B, C, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = BstIter::from_root(self.root.as_deref()).map(|(k, v)| (k, v));
+        f.debug_map().entries(entries).finish()
+    }
+}
+
+/// Consumes the map, yielding its entries in key order
+impl<K, V, B, C, A> IntoIterator for TreeMap<K, V, B, C, A> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<(K, V), A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::from_root(self.root)
+    }
+}
+
+/// Allows a borrowed map to be used in, e.g. for loops
+impl<'a, K, V, B, C, A: Monoid<(K, V)>> IntoIterator for &'a TreeMap<K, V, B, C, A> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = MapIter<'a, K, V, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Allows a mutably borrowed map to be used in, e.g. for loops, to update values
+impl<'a, K, V, B, C> IntoIterator for &'a mut TreeMap<K, V, B, C> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = MapIterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// Collects entries into a map by sorting them by key and building a balanced
+/// tree, which takes O(n log n) time. Of entries with equal keys, the first key
+/// is kept with the last value.
+impl<K, V, B, C, A> FromIterator<(K, V)> for TreeMap<K, V, B, C, A>
+where
+    C: Compare<K> + Default,
+    A: Monoid<(K, V)>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let cmp = C::default();
+        let mut entries: Vec<(K, V)> = iter.into_iter().collect();
+        entries.sort_by(|a, b| cmp.compare(&a.0, &b.0));
+        Self::from_sorted(entries, cmp)
+    }
+}
+
+/// Inserts each entry in turn, as for insert
+impl<K, V, B, C, A> Extend<(K, V)> for TreeMap<K, V, B, C, A>
+where
+    B: Balance,
+    C: Compare<K>,
+    A: Monoid<(K, V)>,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// Constructor for maps using the natural ordering of their keys
+impl<K: cmp::Ord, V, B> TreeMap<K, V, B, Natural> {
+    /// Makes a new, empty map.
+    pub fn new() -> Self {
+        Self::with_comparator(Natural)
+    }
+
+    /// Makes a map of [entries], which must be in ascending key order, in O(n)
+    /// time and with every level of the tree full except possibly the last. Of a
+    /// run of entries with equal keys, the first key is kept with the last value,
+    /// as if they had been inserted in turn.
+    /// Panics if the entries are not in order.
+    pub fn from_sorted_vec(entries: Vec<(K, V)>) -> Self {
+        Self::from_sorted(entries, Natural)
+    }
+
+    /// Makes a map of the entries yielded by [iter], as for from_sorted_vec.
+    pub fn from_sorted_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self::from_sorted_vec(iter.into_iter().collect())
+    }
+}
+
+/// Methods for maps that cache no aggregate, which alone hand out lasting
+/// mutable references to their values: changing a value through one would
+/// leave stale the aggregates cached above it.
+impl<K, V, B, C> TreeMap<K, V, B, C> {
+    /// Makes a new, empty map with keys ordered by [cmp].
+    pub fn with_comparator(cmp: C) -> Self {
+        Self::from_root(None, cmp)
+    }
+
+    /// Gets an iterator over the entries of the map, in key order, with mutable
+    /// references to the values.
+    pub fn iter_mut(&mut self) -> MapIterMut<'_, K, V> {
+        MapIterMut {
+            inner: BstIterMut::from_root(self.root.as_deref_mut()),
+        }
+    }
+
+    /// Gets an iterator over mutable references to the values of the map,
+    /// in key order.
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut {
+            inner: BstIterMut::from_root(self.root.as_deref_mut()),
+        }
+    }
+}
+
+/// Methods for TreeMap that do not compare keys
+impl<K, V, B, C, A: Monoid<(K, V)>> TreeMap<K, V, B, C, A> {
+    /// Makes a map ordered by [cmp] of the tree at [root]
+    fn from_root(root: Option<Box<Bst<(K, V), A>>>, cmp: C) -> Self {
+        Self {
+            root,
+            balance: PhantomData,
+            cmp,
+        }
+    }
+
+    /// Makes a map ordered by [cmp] of [entries], as for from_sorted_vec.
+    fn from_sorted(mut entries: Vec<(K, V)>, cmp: C) -> Self
+    where
+        C: Compare<K>,
+    {
+        // dedup_by passes each entry along with the last one kept before it
+        entries.dedup_by(|entry, kept| match cmp.compare(&kept.0, &entry.0) {
+            cmp::Ordering::Less => false,
+            cmp::Ordering::Equal => {
+                mem::swap(&mut kept.1, &mut entry.1);
+                true
+            }
+            cmp::Ordering::Greater => panic!("entries are not in ascending key order"),
+        });
+        let len = entries.len();
+        Self::from_root(Bst::build_balanced(&mut entries.into_iter(), len), cmp)
+    }
+
+    /// Returns true if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        Bst::size_of(&self.root)
+    }
+
+    /// Gets an iterator over the entries of the map, in key order.
+    pub fn iter(&self) -> MapIter<'_, K, V, A> {
+        MapIter {
+            inner: BstIter::from_root(self.root.as_deref()),
+        }
+    }
+
+    /// Gets an iterator over the keys of the map, in order.
+    pub fn keys(&self) -> Keys<'_, K, V, A> {
+        Keys { inner: self.iter() }
+    }
+
+    /// Gets an iterator over the values of the map, in key order.
+    pub fn values(&self) -> Values<'_, K, V, A> {
+        Values { inner: self.iter() }
+    }
+
+    /// Keeps only the entries for which [f] returns true, visiting them in key
+    /// order. The kept entries are rebuilt into a balanced tree, so this takes
+    /// O(n) time however many are removed.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let kept: Vec<(K, V)> = IntoIter::from_root(self.root.take())
+            .filter_map(|(key, mut value)| f(&key, &mut value).then_some((key, value)))
+            .collect();
+        let len = kept.len();
+        self.root = Bst::build_balanced(&mut kept.into_iter(), len);
+    }
+
+    /// Removes every entry from the map, returning an iterator that yields them
+    /// in key order. The map is empty as soon as this returns, even if the
+    /// iterator is not consumed.
+    pub fn drain(&mut self) -> IntoIter<(K, V), A> {
+        IntoIter::from_root(self.root.take())
+    }
+
+    /// Returns the entry with the least key, or None if the map is empty.
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        Bst::first_of(self.root.as_deref()).map(|(k, v)| (k, v))
+    }
+
+    /// Returns the entry with the [k]th least key, counting from 0, or None if
+    /// the map has no more than [k] entries.
+    pub fn select(&self, k: usize) -> Option<(&K, &V)> {
+        Bst::get_at(self.root.as_deref(), k).map(|(k, v)| (k, v))
+    }
+
+    /// Returns the entry with the greatest key, or None if the map is empty.
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        Bst::last_of(self.root.as_deref()).map(|(k, v)| (k, v))
+    }
+
+    /// Returns the aggregate of all the entries of the map (the identity if it
+    /// is empty), which is cached at the root of its tree, in O(1) time.
+    pub fn aggregate(&self) -> A {
+        Bst::aggregate_of(&self.root)
+    }
+
+    /// Converts the map into one whose tree has the same shape but caches the
+    /// aggregate A2 of its entries in every node instead, e.g.
+    /// `RbMap::new().with_aggregate::<Count>()`, in O(n) time.
+    pub fn with_aggregate<A2: Monoid<(K, V)>>(self) -> TreeMap<K, V, B, C, A2> {
+        TreeMap::from_root(Bst::reaggregate(self.root), self.cmp)
+    }
+}
+
+/// Methods for maps that cache no aggregate and compare keys, which alone
+/// hand out lasting mutable references to their values, as above
+impl<K, V, B: Balance, C: Compare<K>> TreeMap<K, V, B, C> {
+    /// Gets the entry for [key] for in-place manipulation, e.g.
+    /// `*map.entry(key).or_insert(0) += 1`. Finding the entry takes a single
+    /// descent of the tree, which ends at the entry if it is occupied, or else
+    /// yields the in-order index of the vacant entry. Inserting into a vacant
+    /// entry then descends twice more by subtree sizes alone, without comparing
+    /// keys: once to insert at that index and rebalance, and once to reach the
+    /// inserted value, which rebalancing may have moved.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, B, C> {
+        let map: *mut Self = self;
+        // SAFETY: map comes from the unique borrow of self, which outlives the
+        // entry. The descent borrows the tree through it, and that borrow is
+        // kept only by an occupied entry; a vacant entry borrows the map again
+        // only once the descent has returned nothing borrowed, so the two are
+        // never live at once. The borrow checker cannot see that a borrow
+        // returned from one arm of a match is over in the others.
+        let TreeMap { root, cmp, .. } = unsafe { &mut *map };
+        match Bst::get_mut_or_rank_by(root.as_deref_mut(), |(k, _)| cmp.compare(&key, k)) {
+            Ok(entry) => Entry::Occupied(OccupiedEntry { entry }),
+            Err(index) => Entry::Vacant(VacantEntry {
+                key,
+                index,
+                map: unsafe { &mut *map },
+            }),
+        }
+    }
+
+    /// Returns a mutable reference to the value for [key], if present.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::get_mut_by(self.root.as_deref_mut(), |(k, _)| {
+            self.cmp.compare(key, k.borrow())
+        })
+        .map(|(_, v)| v)
+    }
+}
+
+/// Methods for TreeMap, parameterized over its key type, value type, balancing
+/// strategy, key comparator and aggregate.
+impl<K, V, B: Balance, C: Compare<K>, A: Monoid<(K, V)>> TreeMap<K, V, B, C, A> {
+    /// Inserts a key-value pair into the map. If the key was already present,
+    /// its value is replaced (the key itself is kept) and the previous value
+    /// is returned; otherwise returns None. Either way this takes a single
+    /// descent of the tree.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        B::insert(
+            &mut self.root,
+            (key, value),
+            |(key, _), node| self.cmp.compare(key, &node.value.0),
+            |(_, v), (_, value)| mem::replace(v, value),
+        )
+    }
+
+    /// Returns a reference to the value for [key], if present.
+    /// The key may be any borrowed form of the map's key type that the
+    /// comparator can also order.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::get_by(self.root.as_deref(), |(k, _)| {
+            self.cmp.compare(key, k.borrow())
+        })
+        .map(|(_, v)| v)
+    }
+
+    /// Gets an iterator over the entries of the map whose keys lie within
+    /// [range], in key order.
+    pub fn range<Q, R>(&self, range: R) -> MapIter<'_, K, V, A>
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+        R: ops::RangeBounds<Q>,
+    {
+        MapIter {
+            inner: BstIter::from_range(self.root.as_deref(), &range, |bound, (k, _)| {
+                self.cmp.compare(bound, k.borrow())
+            }),
+        }
+    }
+
+    /// Returns the aggregate of the entries of the map whose keys lie within
+    /// [range], e.g. `map.aggregate_range(1000..2000)`, in O(height) time: it is
+    /// combined from the aggregates cached along the paths to the ends of the
+    /// range, without visiting the entries in between.
+    pub fn aggregate_range<Q, R>(&self, range: R) -> A
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+        R: ops::RangeBounds<Q>,
+    {
+        Bst::aggregate_range_by(self.root.as_deref(), &range, |bound, (k, _)| {
+            self.cmp.compare(bound, k.borrow())
+        })
+    }
+
+    /// Returns the entry with the greatest key less than or equal to [key], if any.
+    pub fn floor_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::floor_by(
+            self.root.as_deref(),
+            |(k, _)| self.cmp.compare(key, k.borrow()),
+            true,
+        )
+        .map(|(k, v)| (k, v))
+    }
+
+    /// Returns the number of entries of the map whose keys are less than [key],
+    /// whether or not [key] itself is present.
+    pub fn rank<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::rank_by(self.root.as_deref(), |(k, _)| {
+            self.cmp.compare(key, k.borrow())
+        })
+        .0
+    }
+
+    /// Returns the entry with the least key greater than or equal to [key], if any.
+    pub fn ceiling_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::ceiling_by(
+            self.root.as_deref(),
+            |(k, _)| self.cmp.compare(key, k.borrow()),
+            true,
+        )
+        .map(|(k, v)| (k, v))
+    }
+
+    /// Returns the entry with the greatest key strictly less than [key], if any.
+    pub fn predecessor_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::floor_by(
+            self.root.as_deref(),
+            |(k, _)| self.cmp.compare(key, k.borrow()),
+            false,
+        )
+        .map(|(k, v)| (k, v))
+    }
+
+    /// Returns the entry with the least key strictly greater than [key], if any.
+    pub fn successor_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::ceiling_by(
+            self.root.as_deref(),
+            |(k, _)| self.cmp.compare(key, k.borrow()),
+            false,
+        )
+        .map(|(k, v)| (k, v))
+    }
+
+    /// Returns true if the map contains an entry for [key].
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes and returns the entry with the least key, or None if the map is empty.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        B::take_first(&mut self.root)
+    }
+
+    /// Removes and returns the entry with the greatest key, or None if the map
+    /// is empty.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        B::take_last(&mut self.root)
+    }
+
+    /// Gets an iterator that visits the entries of the map in key order,
+    /// removing and yielding those for which [pred] returns true. [pred] may
+    /// also change the values of the entries it keeps. Entries are only visited
+    /// (and removed) as the iterator advances, so dropping it early leaves the
+    /// rest of the map untouched. Each removal rebalances the tree and takes
+    /// O(height) time.
+    pub fn extract_if<F>(&mut self, pred: F) -> MapExtractIf<'_, K, V, B, C, F, A>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        MapExtractIf {
+            map: self,
+            index: 0,
+            pred,
+        }
+    }
+
+    /// Moves the entries of the map with keys greater than or equal to [key]
+    /// into a new map with the same comparator, which is returned. Takes
+    /// O(height) time in the balanced modes.
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        C: Compare<Q> + Clone,
+        Q: ?Sized,
+    {
+        let after = B::split(&mut self.root, |(k, _)| self.cmp.compare(key, k.borrow()));
+        Self::from_root(after, self.cmp.clone())
+    }
+
+    /// Removes every entry of the map whose key lies within [range], returning
+    /// how many were removed. Takes O(height + k) time in the balanced modes,
+    /// to remove k entries.
+    pub fn remove_range<Q, R>(&mut self, range: R) -> usize
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+        R: ops::RangeBounds<Q>,
+    {
+        let removed = B::take_range(&mut self.root, &range, |bound, (k, _)| {
+            self.cmp.compare(bound, k.borrow())
+        });
+        Bst::size_of(&removed)
+    }
+
+    /// Moves every entry of [other] into this map, leaving [other] empty. For
+    /// a key in both maps, the value from [other] replaces the one in this map,
+    /// as for insert. If every key of one map precedes every key of the other,
+    /// the trees are joined in O(height) time in the balanced modes; otherwise
+    /// they are merged in O(n + m) time.
+    pub fn append(&mut self, other: &mut Self) {
+        B::append(
+            &mut self.root,
+            &mut other.root,
+            |a, b| self.cmp.compare(&a.0, &b.0),
+            |(key, _), (_, value)| (key, value),
+        );
+    }
+
+    /// Removes the entry for [key] from the map, returning its value if present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        let (_, value) = B::take(&mut self.root, |node| {
+            self.cmp.compare(key, node.value.0.borrow())
+        })?;
+        Some(value)
+    }
+}
+
+/// Methods specific to red-black maps
+impl<K, V, C, A> TreeMap<K, V, RedBlack, C, A> {
+    /// Checks that the tree satisfies the red-black invariants, as for
+    /// TreeSet::check_invariants. Panics if any is violated. Takes O(n) time,
+    /// as it visits every node.
+    #[doc(hidden)]
+    pub fn check_invariants(&self) {
+        assert!(
+            self.root.as_ref().is_none_or(|root| !root.red),
+            "root is red"
+        );
+        rb::check(&self.root);
+    }
+}
+
+/// A view into a single entry of a TreeMap, which may be vacant or occupied.
+/// Returned by TreeMap::entry.
+#[derive(Debug)]
+pub enum Entry<'a, K, V, B, C = Natural> {
+    /// No value is stored for the key
+    Vacant(VacantEntry<'a, K, V, B, C>),
+    /// A value is stored for the key
+    Occupied(OccupiedEntry<'a, K, V>),
+}
+
+/// A view into a vacant entry of a TreeMap
+#[derive(Debug)]
+pub struct VacantEntry<'a, K, V, B, C = Natural> {
+    key: K,
+    /// In-order index the entry will have once inserted
+    index: usize,
+    map: &'a mut TreeMap<K, V, B, C>,
+}
+
+/// A view into an occupied entry of a TreeMap
+#[derive(Debug)]
+pub struct OccupiedEntry<'a, K, V> {
+    entry: &'a mut (K, V),
+}
+
+/// Methods for Entry, mirroring those of the entries of std's BTreeMap
+impl<'a, K, V, B: Balance, C: Compare<K>> Entry<'a, K, V, B, C> {
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Occupied(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts [default] if the entry is vacant, and returns a mutable
+    /// reference to the value in the entry.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of [default] if the entry is vacant, and returns a
+    /// mutable reference to the value in the entry. [default] is only called
+    /// if the entry is vacant.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        self.or_insert_with_key(|_| default())
+    }
+
+    /// Like or_insert_with, but [default] is given the entry's key.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    /// Calls [f] on the value if the entry is occupied, then returns the entry
+    /// for further chaining (typically with one of the or_insert methods).
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+/// Default-inserting method for Entry
+impl<'a, K, V: Default, B: Balance, C: Compare<K>> Entry<'a, K, V, B, C> {
+    /// Inserts the default value of V if the entry is vacant, and returns a
+    /// mutable reference to the value in the entry.
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+/// Methods for VacantEntry
+impl<'a, K, V, B: Balance, C: Compare<K>> VacantEntry<'a, K, V, B, C> {
+    /// Returns the key that would be used when inserting.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes back ownership of the key without inserting.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts [value] for the entry's key, returning a mutable reference to it.
+    pub fn insert(self, value: V) -> &'a mut V {
+        let map = self.map;
+        B::insert(
+            &mut map.root,
+            (self.key, value),
+            Bst::locate_insert_at(self.index),
+            |_, _| unreachable!("locate_insert_at never reports Equal"),
+        );
+        let (_, value) =
+            Bst::get_mut_at(map.root.as_deref_mut(), self.index).expect("inserted entry exists");
+        value
+    }
+}
+
+/// Methods for OccupiedEntry
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.entry.0
+    }
+
+    /// Returns a reference to the value of the entry.
+    pub fn get(&self) -> &V {
+        &self.entry.1
+    }
+
+    /// Returns a mutable reference to the value of the entry.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.entry.1
+    }
+
+    /// Converts the entry into a mutable reference to its value, with the
+    /// lifetime of the map.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.entry.1
+    }
+
+    /// Replaces the value of the entry with [value], returning the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(&mut self.entry.1, value)
+    }
+}
+
+/// Iterator over the entries of a TreeMap, in key order
+#[derive(Debug)]
+pub struct MapIter<'a, K, V, A = ()> {
+    inner: BstIter<'a, (K, V), A>,
+}
+
+impl<'a, K, V, A> Iterator for MapIter<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, v)| (k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V, A> DoubleEndedIterator for MapIter<'a, K, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, v)| (k, v))
+    }
+}
+
+impl<'a, K, V, A> ExactSizeIterator for MapIter<'a, K, V, A> {}
+
+/// Iterator over the entries of a TreeMap, in key order, with mutable references
+/// to the values. Keys are not exposed mutably, so the ordering of the map is
+/// preserved.
+#[derive(Debug)]
+pub struct MapIterMut<'a, K, V> {
+    inner: BstIterMut<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for MapIterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, v)| (&*k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for MapIterMut<'a, K, V> {}
+
+/// Iterator over the keys of a TreeMap, in order
+#[derive(Debug)]
+pub struct Keys<'a, K, V, A = ()> {
+    inner: MapIter<'a, K, V, A>,
+}
+
+impl<'a, K, V, A> Iterator for Keys<'a, K, V, A> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V, A> DoubleEndedIterator for Keys<'a, K, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<'a, K, V, A> ExactSizeIterator for Keys<'a, K, V, A> {}
+
+/// Iterator over the values of a TreeMap, in key order
+#[derive(Debug)]
+pub struct Values<'a, K, V, A = ()> {
+    inner: MapIter<'a, K, V, A>,
+}
+
+impl<'a, K, V, A> Iterator for Values<'a, K, V, A> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V, A> DoubleEndedIterator for Values<'a, K, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<'a, K, V, A> ExactSizeIterator for Values<'a, K, V, A> {}
+
+/// Iterator over mutable references to the values of a TreeMap, in key order.
+/// Keys are not exposed mutably, so the ordering of the map is preserved.
+#[derive(Debug)]
+pub struct ValuesMut<'a, K, V> {
+    inner: BstIterMut<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for ValuesMut<'a, K, V> {}
+
+/// Iterator that removes and yields the entries of a TreeMap that match a
+/// predicate, in key order
+#[derive(Debug)]
+pub struct MapExtractIf<'a, K, V, B, C, F, A = ()> {
+    map: &'a mut TreeMap<K, V, B, C, A>,
+    /// In-order index of the next entry to visit, which is also the number of
+    /// entries visited and kept so far
+    index: usize,
+    pred: F,
+}
+
+impl<'a, K, V, B, C, F, A> Iterator for MapExtractIf<'a, K, V, B, C, F, A>
+where
+    B: Balance,
+    F: FnMut(&K, &mut V) -> bool,
+    A: Monoid<(K, V)>,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let pred = &mut self.pred;
+            if Bst::modify_at(&mut self.map.root, self.index, |(k, v)| pred(k, v))? {
+                return B::take(&mut self.map.root, Bst::locate_at(self.index));
+            }
+            self.index += 1;
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.map.len() - self.index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::test_util::{check_sizes, descents, Keyed, Rng};
+
+    fn check_against_btree_map<B: Balance>() {
+        let mut rng = Rng(0x3a9);
+        let mut map: TreeMap<u64, u64, B> = TreeMap::new();
+        let mut expected = BTreeMap::new();
+        for step in 0..5_000 {
+            let key = rng.below(200);
+            match rng.below(6) {
+                0..=2 => assert_eq!(map.insert(key, step), expected.insert(key, step)),
+                3 => assert_eq!(map.remove(&key), expected.remove(&key)),
+                4 => match rng.below(2) {
+                    0 => assert_eq!(map.pop_first(), expected.pop_first()),
+                    _ => assert_eq!(map.pop_last(), expected.pop_last()),
+                },
+                _ => {
+                    if let Some(value) = map.get_mut(&key) {
+                        *value += 1;
+                    }
+                    if let Some(value) = expected.get_mut(&key) {
+                        *value += 1;
+                    }
+                }
+            }
+            assert_eq!(map.get(&key), expected.get(&key));
+            assert_eq!(map.contains_key(&key), expected.contains_key(&key));
+            assert_eq!(map.len(), expected.len());
+            assert_eq!(map.first_key_value(), expected.first_key_value());
+            assert_eq!(map.last_key_value(), expected.last_key_value());
+        }
+        assert!(map.iter().eq(expected.iter()));
+        assert!(map.keys().eq(expected.keys()));
+        for value in map.values_mut() {
+            *value *= 2;
+        }
+        assert!(map.values().copied().eq(expected.values().map(|v| v * 2)));
+    }
+
+    #[test]
+    fn maps_match_btree_map() {
+        check_against_btree_map::<Unbalanced>();
+        check_against_btree_map::<Avl>();
+        check_against_btree_map::<RedBlack>();
+    }
+
+    /// Counts the descents of a map of B that entry and insert make, and the
+    /// keys they compare, against a map whose keys are compared by a counting
+    /// comparator
+    fn check_entry_descents<B: Balance>() {
+        let comparisons = Cell::new(0);
+        let mut map: TreeMap<u64, u64, B, _> = TreeMap::with_comparator(|a: &u64, b: &u64| {
+            comparisons.set(comparisons.get() + 1);
+            a.cmp(b)
+        });
+        let mut rng = Rng(0xe471);
+        for _ in 0..1_000 {
+            let key = 2 * rng.below(1_000);
+            map.insert(key, key);
+        }
+        let key = *map.keys().nth(map.len() / 2).expect("map is not empty");
+        // Run [f] on the map, returning the number of descents it made, having
+        // checked that it compared no more keys than one descent can
+        let mut walks = |f: &mut dyn FnMut(&mut TreeMap<u64, u64, B, _>)| {
+            let height = map.root.as_ref().map_or(0, |root| root.height);
+            comparisons.set(0);
+            let ((), descents) = descents(|| f(&mut map));
+            assert!(comparisons.get() <= height);
+            descents
+        };
+
+        // An occupied entry is found on the one descent
+        assert_eq!(walks(&mut |map| *map.entry(key).or_insert(0) += 1), 1);
+        assert_eq!(
+            walks(&mut |map| assert_eq!(map.get(&key), Some(&(key + 1)))),
+            1
+        );
+        // A vacant entry is found on one descent, then inserted at its index by
+        // subtree sizes and reached again by them, without comparing keys
+        assert_eq!(
+            walks(&mut |map| assert!(matches!(map.entry(key + 1), Entry::Vacant(_)))),
+            1
+        );
+        assert_eq!(walks(&mut |map| *map.entry(key + 1).or_insert(0) += 7), 3);
+        assert_eq!(
+            walks(&mut |map| assert_eq!(map.get(&(key + 1)), Some(&7))),
+            1
+        );
+        // insert replaces or inserts on a single descent
+        assert_eq!(
+            walks(&mut |map| assert_eq!(map.insert(key + 3, 9), None)),
+            1
+        );
+        assert_eq!(
+            walks(&mut |map| assert_eq!(map.insert(key + 3, 10), Some(9))),
+            1
+        );
+        assert_eq!(map.get(&(key + 3)), Some(&10));
+        assert_eq!(map.len(), map.iter().count());
+    }
+
+    #[test]
+    fn entry_and_insert_descend_once() {
+        check_entry_descents::<Unbalanced>();
+        check_entry_descents::<Avl>();
+        check_entry_descents::<RedBlack>();
+    }
+
+    #[test]
+    fn entry_counts_match_btree_map() {
+        let mut rng = Rng(0xc0de);
+        let mut counts = RbMap::new();
+        let mut expected = BTreeMap::new();
+        for _ in 0..10_000 {
+            let key = rng.below(2_000);
+            *counts.entry(key).or_insert(0) += 1;
+            *expected.entry(key).or_insert(0) += 1;
+            let doubled = counts.entry(key + 5_000).or_insert_with(|| 2 * key);
+            assert_eq!(*doubled, 2 * key);
+            counts.check_invariants();
+        }
+        expected.extend(expected.clone().keys().map(|&key| (key + 5_000, 2 * key)));
+        assert!(counts.iter().eq(expected.iter()));
+        assert_eq!(counts.len(), expected.len());
+    }
+
+    #[test]
+    fn mutable_iterators_know_their_length() {
+        let mut map: RbMap<i32, i32> = (0..50).map(|k| (k, k)).collect();
+        let mut iter = map.iter_mut();
+        assert_eq!(iter.len(), 50);
+        for (expected, (key, value)) in (0..10).zip(&mut iter) {
+            assert_eq!(*key, expected);
+            *value *= 10;
+        }
+        assert_eq!(iter.size_hint(), (40, Some(40)));
+        let values: Vec<&mut i32> = map.values_mut().collect();
+        assert_eq!(values.len(), 50);
+        assert_eq!(*values[9], 90);
+        assert_eq!(map.values_mut().skip(45).len(), 5);
+        assert_eq!(RbMap::<i32, i32>::new().iter_mut().len(), 0);
+        for (key, value) in &mut map {
+            *value += key;
+        }
+        assert!(map
+            .values()
+            .copied()
+            .eq((0..50).map(|k| if k < 10 { 11 * k } else { 2 * k })));
+    }
+
+    #[test]
+    fn from_sorted_keeps_the_first_key_with_the_last_value() {
+        let entries = vec![
+            (Keyed(1, 'a'), 10),
+            (Keyed(1, 'b'), 11),
+            (Keyed(1, 'c'), 12),
+            (Keyed(2, 'd'), 20),
+            (Keyed(4, 'e'), 40),
+            (Keyed(4, 'f'), 41),
+        ];
+        let map = RbMap::from_sorted_vec(entries.clone());
+        map.check_invariants();
+        assert!(map.iter().map(|(key, value)| (key.1, *value)).eq([
+            ('a', 12),
+            ('d', 20),
+            ('e', 41)
+        ]));
+        // As if the entries had been inserted in turn
+        let mut inserted = BstMap::new();
+        inserted.extend(entries);
+        assert!(inserted
+            .iter()
+            .map(|(key, value)| (key.1, *value))
+            .eq(map.iter().map(|(key, value)| (key.1, *value))));
+
+        for len in 0..200 {
+            let map = RbMap::from_sorted_iter((0..len).map(|key| (key, key * 2)));
+            map.check_invariants();
+            check_sizes(&map.root);
+            assert!(map
+                .iter()
+                .map(|(key, value)| (*key, *value))
+                .eq((0..len).map(|key| (key, key * 2))));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "entries are not in ascending key order")]
+    fn from_sorted_rejects_unsorted_keys() {
+        RbMap::from_sorted_vec(vec![(1, 'a'), (2, 'b'), (2, 'c'), (0, 'd')]);
+    }
+}
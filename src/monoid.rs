@@ -0,0 +1,343 @@
+use std::cmp;
+use std::iter;
+
+/// An aggregate of a run of values of type E that every node of a tree can
+/// cache for its subtree, such as their sum, minimum or count. Aggregates must
+/// form a monoid: combine must be associative, with identity on either side
+/// leaving the other unchanged. Then the aggregate of a whole tree is at its
+/// root, and that of any range of it can be combined from O(height) nodes.
+/// The unit type is the aggregate of trees that cache nothing; Sum, Min, Max
+/// and Count are provided, and others, e.g. a sum of the values of a map's
+/// entries, can be written in the same way.
+pub trait Monoid<E>: Clone {
+    /// The aggregate of no values.
+    fn identity() -> Self;
+
+    /// The aggregate of the single value [value].
+    fn of(value: &E) -> Self;
+
+    /// The aggregate of the values aggregated by [left] followed by those
+    /// aggregated by [right].
+    fn combine(left: &Self, right: &Self) -> Self;
+}
+
+/// Caches nothing, at no cost
+impl<E> Monoid<E> for () {
+    fn identity() -> Self {}
+
+    fn of(_: &E) -> Self {}
+
+    fn combine(_: &Self, _: &Self) -> Self {}
+}
+
+/// Sum of the values, for any type that the standard library can sum by
+/// reference, e.g. integers, floats and `Duration`. The sum of no values is
+/// that of an empty iterator, e.g. 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Sum<T>(pub T);
+
+impl<T: Clone + for<'a> iter::Sum<&'a T>> Monoid<T> for Sum<T> {
+    fn identity() -> Self {
+        Sum(iter::empty().sum())
+    }
+
+    fn of(value: &T) -> Self {
+        Sum(value.clone())
+    }
+
+    fn combine(left: &Self, right: &Self) -> Self {
+        Sum([&left.0, &right.0].into_iter().sum())
+    }
+}
+
+/// Least of the values by their natural ordering, or None for no values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Min<T>(pub Option<T>);
+
+impl<T: cmp::Ord + Clone> Monoid<T> for Min<T> {
+    fn identity() -> Self {
+        Min(None)
+    }
+
+    fn of(value: &T) -> Self {
+        Min(Some(value.clone()))
+    }
+
+    fn combine(left: &Self, right: &Self) -> Self {
+        match (&left.0, &right.0) {
+            (Some(a), Some(b)) => Min(Some(cmp::min(a, b).clone())),
+            (Some(_), None) => left.clone(),
+            (None, _) => right.clone(),
+        }
+    }
+}
+
+/// Greatest of the values by their natural ordering, or None for no values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Max<T>(pub Option<T>);
+
+impl<T: cmp::Ord + Clone> Monoid<T> for Max<T> {
+    fn identity() -> Self {
+        Max(None)
+    }
+
+    fn of(value: &T) -> Self {
+        Max(Some(value.clone()))
+    }
+
+    fn combine(left: &Self, right: &Self) -> Self {
+        match (&left.0, &right.0) {
+            (Some(a), Some(b)) => Max(Some(cmp::max(a, b).clone())),
+            (Some(_), None) => left.clone(),
+            (None, _) => right.clone(),
+        }
+    }
+}
+
+/// Number of values, of any type. A tree already knows its length, but
+/// counting lets aggregate_range say how many values lie within a range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Count(pub usize);
+
+impl<E> Monoid<E> for Count {
+    fn identity() -> Self {
+        Count(0)
+    }
+
+    fn of(_: &E) -> Self {
+        Count(1)
+    }
+
+    fn combine(left: &Self, right: &Self) -> Self {
+        Count(left.0 + right.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+    use crate::test_util::{Checked, Rng};
+    use crate::{
+        Avl, AvlSet, Bst, BstIter, Natural, RbMap, RbSet, RedBlack, TreeMap, TreeSet, Unbalanced,
+    };
+    use std::ops;
+
+    #[test]
+    fn provided_monoids() {
+        let mut tree = Bst::new(5).with_aggregate::<Sum<i32>>();
+        tree.extend([-3, 8, 1]);
+        assert_eq!(tree.aggregate(), &Sum(11));
+        assert_eq!(tree.aggregate_range(0..6), Sum(6));
+
+        let set: AvlSet<i64, Natural, Min<i64>> = AvlSet::new().with_aggregate();
+        assert_eq!(set.aggregate(), Min(None));
+        let set = set.with_aggregate::<Max<i64>>();
+        assert_eq!(set.aggregate(), Max(None));
+
+        let set: RbSet<u8> = (1..=9).collect();
+        let set = set.with_aggregate::<Min<u8>>();
+        assert_eq!(set.aggregate(), Min(Some(1)));
+        assert_eq!(set.aggregate_range(4..), Min(Some(4)));
+        let set = set.with_aggregate::<Max<u8>>();
+        assert_eq!(set.aggregate(), Max(Some(9)));
+        assert_eq!(set.aggregate_range(..4), Max(Some(3)));
+        assert_eq!(set.aggregate_range(20..), Max(None));
+
+        let map: RbMap<&str, f64> = [("a", 0.5), ("b", 1.5)].into_iter().collect();
+        let map = map.with_aggregate::<Count>();
+        assert_eq!(map.aggregate(), Count(2));
+        assert_eq!(
+            map.aggregate_range::<str, _>((ops::Bound::Excluded("a"), ops::Bound::Unbounded)),
+            Count(1)
+        );
+    }
+
+    /// Polynomial hash of a run of values, which depends on their order as well
+    /// as on the values, so that combining aggregates in the wrong order shows
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Digest {
+        hash: u64,
+        /// BASE raised to the number of values hashed
+        scale: u64,
+    }
+
+    impl Digest {
+        const MODULUS: u64 = (1 << 61) - 1;
+        const BASE: u64 = 1_000_003;
+
+        fn mul(a: u64, b: u64) -> u64 {
+            (a as u128 * b as u128 % Self::MODULUS as u128) as u64
+        }
+    }
+
+    impl Monoid<u64> for Digest {
+        fn identity() -> Self {
+            Digest { hash: 0, scale: 1 }
+        }
+
+        fn of(value: &u64) -> Self {
+            Digest {
+                hash: value % Self::MODULUS + 1,
+                scale: Self::BASE,
+            }
+        }
+
+        fn combine(left: &Self, right: &Self) -> Self {
+            Digest {
+                hash: (Self::mul(left.hash, right.scale) + right.hash) % Self::MODULUS,
+                scale: Self::mul(left.scale, right.scale),
+            }
+        }
+    }
+
+    /// Hashes an entry of a map by both its key and its value
+    impl Monoid<(u64, u64)> for Digest {
+        fn identity() -> Self {
+            <Digest as Monoid<u64>>::identity()
+        }
+
+        fn of((key, value): &(u64, u64)) -> Self {
+            <Digest as Monoid<u64>>::of(&(key * 1_000_000 + value))
+        }
+
+        fn combine(left: &Self, right: &Self) -> Self {
+            <Digest as Monoid<u64>>::combine(left, right)
+        }
+    }
+
+    /// Aggregate of [values], folded one at a time
+    fn fold<'a, E: 'a, A: Monoid<E>>(values: impl Iterator<Item = &'a E>) -> A {
+        values.fold(A::identity(), |total, value| {
+            A::combine(&total, &A::of(value))
+        })
+    }
+
+    /// Checks that the aggregate cached in every node below [link] is that of
+    /// its subtree. Returns the aggregate of the subtree.
+    fn check_aggregates<E, A>(link: &Option<Box<Bst<E, A>>>) -> A
+    where
+        A: Monoid<E> + PartialEq + fmt::Debug,
+    {
+        match link {
+            None => A::identity(),
+            Some(node) => {
+                let left = check_aggregates(&node.left);
+                let right = check_aggregates(&node.right);
+                let expected = A::combine(&A::combine(&left, &A::of(&node.value)), &right);
+                assert_eq!(node.aggregate, expected);
+                expected
+            }
+        }
+    }
+
+    /// Checks the aggregates of [set], as a whole and over random ranges,
+    /// against folds of its values
+    fn check_set_digest<B: Checked>(set: &TreeSet<u64, B, Natural, Digest>, rng: &mut Rng) {
+        B::check(&set.root);
+        check_aggregates(&set.root);
+        assert_eq!(set.aggregate(), fold(set.iter()));
+        for _ in 0..5 {
+            let (start, end) = (rng.below(1_100), rng.below(1_100));
+            let within = set.iter().filter(|v| (start..=end).contains(*v));
+            assert_eq!(set.aggregate_range(start..=end), fold(within));
+            let within = set.iter().filter(|v| **v < end);
+            assert_eq!(set.aggregate_range(..end), fold(within));
+        }
+    }
+
+    /// Changes sets and maps of B in every way that reshapes their trees,
+    /// checking the cached aggregates after each change
+    fn check_cached_aggregates<B: Checked>() {
+        let mut rng = Rng(0xa99e);
+        let mut set: TreeSet<u64, B, Natural, Digest> = TreeSet::default();
+        for _ in 0..2_000 {
+            let key = rng.below(1_000);
+            match rng.below(6) {
+                0..=2 => {
+                    set.insert(key);
+                }
+                3 => {
+                    set.remove(&key);
+                }
+                4 => {
+                    set.pop_first();
+                }
+                _ => {
+                    set.pop_last();
+                }
+            }
+            check_set_digest(&set, &mut rng);
+        }
+        let len = set.len();
+
+        let key = rng.below(1_000);
+        let mut after = set.split_off(&key);
+        check_set_digest(&set, &mut rng);
+        check_set_digest(&after, &mut rng);
+        set.append(&mut after);
+        assert_eq!(set.len(), len);
+        check_set_digest(&set, &mut rng);
+
+        let mut odd: TreeSet<u64, B, Natural, Digest> = TreeSet::default();
+        odd.extend((0..500).map(|v| v * 2 + 1));
+        set.append(&mut odd);
+        check_set_digest(&set, &mut rng);
+
+        set.remove_range(200..400);
+        check_set_digest(&set, &mut rng);
+        let mut extract = set.extract_if(|v| v % 3 == 0);
+        extract.next();
+        extract.next();
+        check_set_digest(&set, &mut rng);
+        set.extract_if(|v| v % 5 == 0).for_each(drop);
+        check_set_digest(&set, &mut rng);
+        set.retain(|v| v % 7 != 0);
+        check_set_digest(&set, &mut rng);
+        assert!(set.update(&1, |v| *v = 1_050));
+        check_set_digest(&set, &mut rng);
+
+        let plain: TreeSet<u64, B> = (0..300).map(|_| rng.below(1_000)).collect();
+        check_set_digest(&plain.with_aggregate(), &mut rng);
+
+        let mut map: TreeMap<u64, u64, B, Natural, Digest> = TreeMap::default();
+        for step in 0..2_000 {
+            let key = rng.below(300);
+            match rng.below(4) {
+                0..=2 => {
+                    map.insert(key, step);
+                }
+                _ => {
+                    map.remove(&key);
+                }
+            }
+            B::check(&map.root);
+            check_aggregates(&map.root);
+            assert_eq!(
+                map.aggregate(),
+                fold(BstIter::from_root(map.root.as_deref()))
+            );
+            let within = BstIter::from_root(map.root.as_deref()).filter(|(k, _)| *k >= key);
+            assert_eq!(map.aggregate_range(key..), fold(within));
+        }
+        // Values changed by the predicate of extract_if are reaggregated
+        map.extract_if(|k, v| {
+            *v += 1;
+            k % 2 == 0
+        })
+        .for_each(drop);
+        check_aggregates(&map.root);
+        assert_eq!(
+            map.aggregate(),
+            fold(BstIter::from_root(map.root.as_deref()))
+        );
+    }
+
+    #[test]
+    fn cached_aggregates_match_folds() {
+        check_cached_aggregates::<Unbalanced>();
+        check_cached_aggregates::<Avl>();
+        check_cached_aggregates::<RedBlack>();
+    }
+}
@@ -0,0 +1,356 @@
+use std::borrow::Borrow;
+use std::cmp;
+use std::fmt;
+use std::iter;
+
+use crate::{Avl, Balance, Compare, MapIter, Natural, RedBlack, TreeMap, Unbalanced};
+
+/// An ordered multiset of elements of type E: like a TreeSet, but inserting an
+/// element that is already present increments its count instead of being
+/// rejected. Each distinct element is stored once, with its count, in a
+/// TreeMap balanced by B and ordered by C.
+#[derive(Clone)]
+pub struct TreeMultiset<E, B, C = Natural> {
+    /// Count of each distinct element; counts are never zero
+    counts: TreeMap<E, usize, B, C>,
+    /// Total number of elements, counting multiplicity
+    len: usize,
+}
+
+/// A multiset backed by a plain, never-rebalanced binary search tree
+pub type BstMultiset<E, C = Natural> = TreeMultiset<E, Unbalanced, C>;
+
+/// A multiset backed by an AVL tree
+pub type AvlMultiset<E, C = Natural> = TreeMultiset<E, Avl, C>;
+
+/// A multiset backed by a red-black tree
+pub type RbMultiset<E, C = Natural> = TreeMultiset<E, RedBlack, C>;
+
+/// An empty multiset is the default
+impl<E, B, C: Default> Default for TreeMultiset<E, B, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+/// Print space-separated in-order traversal of the multiset, repeating each
+/// element as many times as it was inserted
+impl<E: fmt::Display, B, C> fmt::Display for TreeMultiset<E, B, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, value) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the multiset as its elements, in order and with repetition, e.g.
+/// `{1, 1, 2}`, as for TreeSet
+impl<E: fmt::Debug, B, C> fmt::Debug for TreeMultiset<E, B, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// Allows a borrowed multiset to be used in, e.g. for loops
+impl<'a, E, B, C> IntoIterator for &'a TreeMultiset<E, B, C> {
+    type Item = &'a E;
+    type IntoIter = MultisetIter<'a, E>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Collects values into a multiset by inserting each in turn
+impl<E, B: Balance, C: Compare<E> + Default> FromIterator<E> for TreeMultiset<E, B, C> {
+    fn from_iter<I: IntoIterator<Item = E>>(iter: I) -> Self {
+        let mut multiset = Self::default();
+        multiset.extend(iter);
+        multiset
+    }
+}
+
+/// Inserts each value in turn, as for insert
+impl<E, B: Balance, C: Compare<E>> Extend<E> for TreeMultiset<E, B, C> {
+    fn extend<I: IntoIterator<Item = E>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+/// Constructor for multisets using the natural ordering of their elements
+impl<E: cmp::Ord, B> TreeMultiset<E, B, Natural> {
+    /// Makes a new, empty multiset.
+    pub fn new() -> Self {
+        Self::with_comparator(Natural)
+    }
+}
+
+/// Methods for TreeMultiset that do not compare elements
+impl<E, B, C> TreeMultiset<E, B, C> {
+    /// Makes a new, empty multiset ordered by [cmp].
+    pub fn with_comparator(cmp: C) -> Self {
+        Self {
+            counts: TreeMap::with_comparator(cmp),
+            len: 0,
+        }
+    }
+
+    /// Returns true if the multiset contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of elements in the multiset, counting multiplicity.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of distinct elements in the multiset.
+    pub fn distinct_len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Gets an iterator over the elements of the multiset in order, yielding
+    /// each element as many times as it was inserted.
+    pub fn iter(&self) -> MultisetIter<'_, E> {
+        MultisetIter {
+            counts: self.counts.iter(),
+            current: None,
+            current_back: None,
+            remaining: self.len,
+        }
+    }
+
+    /// Gets an iterator over the distinct elements of the multiset in order,
+    /// together with their counts.
+    pub fn iter_counts(&self) -> MapIter<'_, E, usize> {
+        self.counts.iter()
+    }
+}
+
+/// Methods for TreeMultiset, parameterized over its element type, balancing
+/// strategy and comparator.
+impl<E, B: Balance, C: Compare<E>> TreeMultiset<E, B, C> {
+    /// Inserts one occurrence of the value. If an equal element is already
+    /// present, its count is incremented and [new_val] is dropped.
+    /// Returns the count of the element after insertion.
+    pub fn insert(&mut self, new_val: E) -> usize {
+        let count = self.counts.entry(new_val).or_insert(0);
+        *count += 1;
+        self.len += 1;
+        *count
+    }
+
+    /// Returns the number of occurrences of [key] in the multiset.
+    pub fn count<Q>(&self, key: &Q) -> usize
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.counts.get(key).copied().unwrap_or(0)
+    }
+
+    /// Returns true if the multiset contains at least one occurrence of [key].
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.counts.contains_key(key)
+    }
+
+    /// Removes one occurrence of [key] from the multiset.
+    /// Returns true if removed, false if not present.
+    pub fn remove_one<Q>(&mut self, key: &Q) -> bool
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        match self.counts.get_mut(key) {
+            Some(count) if *count > 1 => *count -= 1,
+            Some(_) => {
+                self.counts.remove(key);
+            }
+            None => return false,
+        }
+        self.len -= 1;
+        true
+    }
+
+    /// Removes every occurrence of [key] from the multiset.
+    /// Returns the number of occurrences removed.
+    pub fn remove_all<Q>(&mut self, key: &Q) -> usize
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        let removed = self.counts.remove(key).unwrap_or(0);
+        self.len -= removed;
+        removed
+    }
+}
+
+/// Methods specific to red-black multisets
+impl<E, C> TreeMultiset<E, RedBlack, C> {
+    /// Checks that the tree of distinct elements satisfies the red-black
+    /// invariants, as for TreeSet::check_invariants. Panics if any is violated.
+    #[doc(hidden)]
+    pub fn check_invariants(&self) {
+        self.counts.check_invariants();
+    }
+}
+
+/// Sum method for TreeMultiset, with the same requirements as for Bst.
+/// Each element is added as many times as it occurs; the sum of an empty
+/// multiset is the identity, e.g. 0.
+impl<E, B, C> TreeMultiset<E, B, C> {
+    /// Sums the elements of the multiset, counting multiplicity.
+    pub fn sum<'a>(&'a self) -> E
+    where
+        E: iter::Sum<&'a E>,
+    {
+        self.iter().sum()
+    }
+}
+
+/// Iterator over the elements of a TreeMultiset, in order and with repetition
+#[derive(Debug)]
+pub struct MultisetIter<'a, E> {
+    /// Iterator over the distinct elements and their counts
+    counts: MapIter<'a, E, usize>,
+    /// Element currently being repeated, and how many more times to yield it
+    current: Option<(&'a E, usize)>,
+    /// Element currently being repeated from the back, and how many more times
+    current_back: Option<(&'a E, usize)>,
+    /// Number of elements left to yield from either end, counting multiplicity
+    remaining: usize,
+}
+
+impl<'a, E> Iterator for MultisetIter<'a, E> {
+    type Item = &'a E;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.current.as_mut() {
+                Some((value, remaining)) if *remaining > 0 => {
+                    *remaining -= 1;
+                    self.remaining -= 1;
+                    return Some(*value);
+                }
+                _ => match self.counts.next() {
+                    Some((v, &c)) => self.current = Some((v, c)),
+                    // The counts are exhausted, but the back may have stopped
+                    // partway through repeating an element
+                    None => self.current = Some(self.current_back.take()?),
+                },
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, E> DoubleEndedIterator for MultisetIter<'a, E> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.current_back.as_mut() {
+                Some((value, remaining)) if *remaining > 0 => {
+                    *remaining -= 1;
+                    self.remaining -= 1;
+                    return Some(*value);
+                }
+                _ => match self.counts.next_back() {
+                    Some((v, &c)) => self.current_back = Some((v, c)),
+                    None => self.current_back = Some(self.current.take()?),
+                },
+            }
+        }
+    }
+}
+
+impl<'a, E> ExactSizeIterator for MultisetIter<'a, E> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::test_util::Rng;
+
+    /// Inserts and removes values in a multiset of B, checking every query
+    /// against a BTreeMap of counts
+    fn check_against_counts<B: Balance>() {
+        let mut rng = Rng(0x5e75);
+        let mut multiset: TreeMultiset<u64, B> = TreeMultiset::new();
+        let mut expected: BTreeMap<u64, usize> = BTreeMap::new();
+        for _ in 0..5_000 {
+            let key = rng.below(60);
+            match rng.below(8) {
+                0..=4 => {
+                    let count = expected.entry(key).or_insert(0);
+                    *count += 1;
+                    assert_eq!(multiset.insert(key), *count);
+                }
+                5 | 6 => {
+                    let removed = match expected.get_mut(&key) {
+                        Some(count) if *count > 1 => {
+                            *count -= 1;
+                            true
+                        }
+                        Some(_) => expected.remove(&key).is_some(),
+                        None => false,
+                    };
+                    assert_eq!(multiset.remove_one(&key), removed);
+                }
+                _ => {
+                    let removed = expected.remove(&key).unwrap_or(0);
+                    assert_eq!(multiset.remove_all(&key), removed);
+                    assert_eq!(multiset.remove_all(&key), 0);
+                }
+            }
+            let probe = rng.below(70);
+            let count = expected.get(&probe).copied().unwrap_or(0);
+            assert_eq!(multiset.count(&probe), count);
+            assert_eq!(multiset.contains(&probe), count > 0);
+            assert_eq!(multiset.distinct_len(), expected.len());
+            assert_eq!(multiset.len(), expected.values().sum::<usize>());
+        }
+        assert!(multiset
+            .iter_counts()
+            .map(|(value, count)| (*value, *count))
+            .eq(expected.iter().map(|(value, count)| (*value, *count))));
+        let repeated = expected
+            .iter()
+            .flat_map(|(value, count)| iter::repeat_n(value, *count));
+        assert!(multiset.iter().eq(repeated));
+    }
+
+    #[test]
+    fn counts_match_btree_map() {
+        check_against_counts::<Unbalanced>();
+        check_against_counts::<Avl>();
+        check_against_counts::<RedBlack>();
+    }
+
+    #[test]
+    fn debug_lists_every_occurrence() {
+        let multiset: AvlMultiset<i32> = [2, 1, 2, 3, 1, 2].into_iter().collect();
+        assert_eq!(format!("{:?}", multiset), "{1, 1, 2, 2, 2, 3}");
+        assert_eq!(format!("{:?}", RbMultiset::<i32>::new()), "{}");
+        let mut words = BstMultiset::new();
+        words.extend(["b", "a", "b"]);
+        assert_eq!(format!("{:?}", words), r#"{"a", "b", "b"}"#);
+    }
+}
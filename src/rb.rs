@@ -0,0 +1,571 @@
+use std::cmp::Ordering;
+use std::mem;
+
+use crate::balance::{Balance, Sealed};
+use crate::{count_descent, Bst, Monoid};
+
+/// Red-black tree: every node is red or black, no red node has a red child,
+/// the root is black, and every path from a node down to an empty subtree
+/// passes through the same number of black nodes. This keeps the height of a
+/// tree of n elements under 2 * log2(n + 1) while needing fewer rotations
+/// than Avl on writes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RedBlack;
+
+impl Sealed for RedBlack {}
+
+impl Balance for RedBlack {
+    fn insert<E, A: Monoid<E>, F, M, R>(
+        root: &mut Option<Box<Bst<E, A>>>,
+        new_val: E,
+        mut locate: F,
+        merge: M,
+    ) -> Option<R>
+    where
+        F: FnMut(&E, &Bst<E, A>) -> Ordering,
+        M: FnOnce(&mut E, E) -> R,
+    {
+        count_descent();
+        let merged = insert(root, new_val, &mut locate, merge);
+        set_black(root);
+        merged
+    }
+
+    fn take<E, A: Monoid<E>, F>(root: &mut Option<Box<Bst<E, A>>>, mut locate: F) -> Option<E>
+    where
+        F: FnMut(&Bst<E, A>) -> Ordering,
+    {
+        count_descent();
+        let (taken, _) = take(root, &mut locate);
+        set_black(root);
+        taken
+    }
+
+    fn take_first<E, A: Monoid<E>>(root: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+        count_descent();
+        let (taken, _) = take_first(root);
+        set_black(root);
+        taken
+    }
+
+    fn take_last<E, A: Monoid<E>>(root: &mut Option<Box<Bst<E, A>>>) -> Option<E> {
+        count_descent();
+        let (taken, _) = take_last(root);
+        set_black(root);
+        taken
+    }
+
+    fn join<E, A: Monoid<E>>(
+        left: Option<Box<Bst<E, A>>>,
+        mid: E,
+        right: Option<Box<Bst<E, A>>>,
+    ) -> Box<Bst<E, A>> {
+        let (left_height, right_height) = (black_height(&left), black_height(&right));
+        join(left, left_height, mid, right, right_height).0
+    }
+
+    /// As for the provided split, but tracking the black height of each piece
+    /// of the path so that the joins need not walk down to find it.
+    fn split<E, A: Monoid<E>, F>(
+        root: &mut Option<Box<Bst<E, A>>>,
+        mut locate: F,
+    ) -> Option<Box<Bst<E, A>>>
+    where
+        F: FnMut(&E) -> Ordering,
+    {
+        let mut height = black_height(root);
+        let mut before = vec![];
+        let mut after = vec![];
+        let mut node = root.take();
+        while let Some(current) = node {
+            let Bst {
+                value,
+                left,
+                right,
+                red,
+                ..
+            } = *current;
+            height -= usize::from(!red);
+            if locate(&value) == Ordering::Greater {
+                before.push((left.into_inner(), height, value));
+                node = right.into_inner();
+            } else {
+                after.push((value, right.into_inner(), height));
+                node = left.into_inner();
+            }
+        }
+        let mut root_height = 0;
+        for (subtree, height, value) in before.into_iter().rev() {
+            let (joined, joined_height) = join(subtree, height, value, root.take(), root_height);
+            (*root, root_height) = (Some(joined), joined_height);
+        }
+        let (mut rest, mut rest_height) = (None, 0);
+        for (value, subtree, height) in after.into_iter().rev() {
+            let (joined, joined_height) = join(rest, rest_height, value, subtree, height);
+            (rest, rest_height) = (Some(joined), joined_height);
+        }
+        rest
+    }
+}
+
+/// Whether the (possibly empty) subtree at [link] has a red root
+fn is_red<E, A>(link: &Option<Box<Bst<E, A>>>) -> bool {
+    link.as_ref().is_some_and(|node| node.red)
+}
+
+/// Recursively inserts [new_val] as a red leaf below [link] where [locate]
+/// directs, repairing red-red violations on the way back up if the value was
+/// inserted, or merges it into an equal value with [merge] and only updates
+/// the aggregates above that.
+fn insert<E, A: Monoid<E>, F, M, R>(
+    link: &mut Option<Box<Bst<E, A>>>,
+    new_val: E,
+    locate: &mut F,
+    merge: M,
+) -> Option<R>
+where
+    F: FnMut(&E, &Bst<E, A>) -> Ordering,
+    M: FnOnce(&mut E, E) -> R,
+{
+    let node = match link {
+        Some(node) => node,
+        None => {
+            let mut leaf = Bst::with_children(new_val, None, None);
+            leaf.red = true;
+            *link = Some(Box::new(leaf));
+            return None;
+        }
+    };
+    let merged = match locate(&new_val, node) {
+        Ordering::Less => insert(&mut node.left, new_val, locate, merge),
+        Ordering::Greater => insert(&mut node.right, new_val, locate, merge),
+        Ordering::Equal => Some(merge(&mut node.value, new_val)),
+    };
+    match merged {
+        Some(_) => node.update_aggregate(),
+        None => {
+            node.update();
+            fix_insert(link);
+        }
+    }
+    merged
+}
+
+/// Repairs a red child of the root of [link] that itself has a red child.
+/// If both children of the root are red, the root takes their redness
+/// (possibly moving the violation up a level); otherwise the three nodes are
+/// rotated so that the middle one is a black root with two red children.
+fn fix_insert<E, A: Monoid<E>>(link: &mut Option<Box<Bst<E, A>>>) {
+    let mut node = match link.take() {
+        Some(node) => node,
+        None => return,
+    };
+    let left_violates = node
+        .left
+        .as_ref()
+        .is_some_and(|left| left.red && (is_red(&left.left) || is_red(&left.right)));
+    let right_violates = node
+        .right
+        .as_ref()
+        .is_some_and(|right| right.red && (is_red(&right.left) || is_red(&right.right)));
+    if left_violates || right_violates {
+        if is_red(&node.left) && is_red(&node.right) {
+            node.red = true;
+            set_black(&mut node.left);
+            set_black(&mut node.right);
+        } else {
+            if left_violates {
+                let left = node.left.take().expect("violating child exists");
+                *node.left = Some(if is_red(&left.right) {
+                    Bst::rotate_left(left)
+                } else {
+                    left
+                });
+                node = Bst::rotate_right(node);
+            } else {
+                let right = node.right.take().expect("violating child exists");
+                *node.right = Some(if is_red(&right.left) {
+                    Bst::rotate_right(right)
+                } else {
+                    right
+                });
+                node = Bst::rotate_left(node);
+            }
+            node.red = false;
+            set_red(&mut node.left);
+            set_red(&mut node.right);
+        }
+    }
+    *link = Some(node);
+}
+
+/// Recursively removes the element that [locate] finds below [link].
+/// Returns the removed element along with whether the black height of the
+/// subtree at [link] decreased by one (which the caller must repair).
+fn take<E, A: Monoid<E>, F>(link: &mut Option<Box<Bst<E, A>>>, locate: &mut F) -> (Option<E>, bool)
+where
+    F: FnMut(&Bst<E, A>) -> Ordering,
+{
+    let node = match link.as_mut() {
+        Some(node) => node,
+        None => return (None, false),
+    };
+    match locate(node) {
+        Ordering::Less => {
+            let (taken, short) = take(&mut node.left, locate);
+            node.update();
+            (taken, short && fix_left(link))
+        }
+        Ordering::Greater => {
+            let (taken, short) = take(&mut node.right, locate);
+            node.update();
+            (taken, short && fix_right(link))
+        }
+        Ordering::Equal => {
+            if node.left.is_some() && node.right.is_some() {
+                let (successor, short) = take_first(&mut node.right);
+                let successor = successor.expect("right subtree is not empty");
+                let value = mem::replace(&mut node.value, successor);
+                node.update();
+                (Some(value), short && fix_right(link))
+            } else {
+                let (value, short) = unlink(link);
+                (value, short)
+            }
+        }
+    }
+}
+
+/// Removes the least element below [link], returning it along with whether
+/// the black height of the subtree at [link] decreased.
+fn take_first<E, A: Monoid<E>>(link: &mut Option<Box<Bst<E, A>>>) -> (Option<E>, bool) {
+    let node = match link.as_mut() {
+        Some(node) => node,
+        None => return (None, false),
+    };
+    if node.left.is_some() {
+        let (taken, short) = take_first(&mut node.left);
+        node.update();
+        (taken, short && fix_left(link))
+    } else {
+        unlink(link)
+    }
+}
+
+/// Removes the greatest element below [link], returning it along with whether
+/// the black height of the subtree at [link] decreased.
+fn take_last<E, A: Monoid<E>>(link: &mut Option<Box<Bst<E, A>>>) -> (Option<E>, bool) {
+    let node = match link.as_mut() {
+        Some(node) => node,
+        None => return (None, false),
+    };
+    if node.right.is_some() {
+        let (taken, short) = take_last(&mut node.right);
+        node.update();
+        (taken, short && fix_right(link))
+    } else {
+        unlink(link)
+    }
+}
+
+/// Number of black nodes on each path from the root of [link] down to an empty
+/// subtree, found by counting them on the leftmost path.
+fn black_height<E, A>(link: &Option<Box<Bst<E, A>>>) -> usize {
+    let mut node = link.as_deref();
+    let mut height = 0;
+    while let Some(current) = node {
+        height += usize::from(!current.red);
+        node = current.left.as_deref();
+    }
+    height
+}
+
+/// Joins [left], [mid] and [right], whose black heights are [left_height] and
+/// [right_height]. Both roots are first colored black; then [mid] becomes a red
+/// node joining the shorter tree to a black subtree of the same black height
+/// on the inner spine of the taller one, and red-red violations are repaired
+/// as for insert. Returns the joined tree, which has a black root, and its
+/// black height. Takes time proportional to the difference in black heights.
+fn join<E, A: Monoid<E>>(
+    mut left: Option<Box<Bst<E, A>>>,
+    mut left_height: usize,
+    mid: E,
+    mut right: Option<Box<Bst<E, A>>>,
+    mut right_height: usize,
+) -> (Box<Bst<E, A>>, usize) {
+    if is_red(&left) {
+        set_black(&mut left);
+        left_height += 1;
+    }
+    if is_red(&right) {
+        set_black(&mut right);
+        right_height += 1;
+    }
+    let (mut root, mut height) = if left_height >= right_height {
+        join_right(&mut left, left_height, mid, right, right_height);
+        (left, left_height)
+    } else {
+        join_left(&mut right, right_height, mid, left, left_height);
+        (right, right_height)
+    };
+    if is_red(&root) {
+        set_black(&mut root);
+        height += 1;
+    }
+    (root.expect("joined tree is not empty"), height)
+}
+
+/// Recursively descends the right spine of the tree at [link], of black height
+/// [height], to the first black or empty subtree of black height
+/// [right_height], and replaces it with a red node of [mid] above it and
+/// [right] (which has a black root). Repairs red-red violations on the way
+/// back up.
+fn join_right<E, A: Monoid<E>>(
+    link: &mut Option<Box<Bst<E, A>>>,
+    height: usize,
+    mid: E,
+    right: Option<Box<Bst<E, A>>>,
+    right_height: usize,
+) {
+    if height == right_height && !is_red(link) {
+        let mut node = Bst::with_children(mid, link.take(), right);
+        node.red = true;
+        *link = Some(Box::new(node));
+        return;
+    }
+    let node = link.as_mut().expect("taller tree is not empty");
+    let height = height - usize::from(!node.red);
+    join_right(&mut node.right, height, mid, right, right_height);
+    node.update();
+    fix_insert(link);
+}
+
+/// Mirror image of join_right, descending the left spine of the tree at [link]
+/// to join [left] and [mid] onto it.
+fn join_left<E, A: Monoid<E>>(
+    link: &mut Option<Box<Bst<E, A>>>,
+    height: usize,
+    mid: E,
+    left: Option<Box<Bst<E, A>>>,
+    left_height: usize,
+) {
+    if height == left_height && !is_red(link) {
+        let mut node = Bst::with_children(mid, left, link.take());
+        node.red = true;
+        *link = Some(Box::new(node));
+        return;
+    }
+    let node = link.as_mut().expect("taller tree is not empty");
+    let height = height - usize::from(!node.red);
+    join_left(&mut node.left, height, mid, left, left_height);
+    node.update();
+    fix_insert(link);
+}
+
+/// Removes the root of [link], which has at most one child. In a valid tree
+/// such a child is a red leaf, which is recolored black to take its place.
+/// Returns the removed value and whether the black height decreased.
+fn unlink<E, A: Monoid<E>>(link: &mut Option<Box<Bst<E, A>>>) -> (Option<E>, bool) {
+    let node = match link.take() {
+        Some(node) => node,
+        None => return (None, false),
+    };
+    let Bst {
+        value,
+        left,
+        right,
+        red,
+        ..
+    } = *node;
+    *link = left.into_inner().or(right.into_inner());
+    let short = match link.as_mut() {
+        Some(child) => {
+            child.red = false;
+            false
+        }
+        None => !red,
+    };
+    (Some(value), short)
+}
+
+/// Repairs the root of [link] after the black height of its left subtree
+/// decreased by one. Returns whether the black height of the whole subtree
+/// at [link] decreased as a result.
+fn fix_left<E, A: Monoid<E>>(link: &mut Option<Box<Bst<E, A>>>) -> bool {
+    let mut node = link.take().expect("repaired subtree is not empty");
+    if is_red(&node.left) {
+        set_black(&mut node.left);
+        *link = Some(node);
+        return false;
+    }
+    if is_red(&node.right) {
+        // Red sibling: rotate it up so the short side has a black sibling.
+        node = Bst::rotate_left(node);
+        node.red = false;
+        set_red(&mut node.left);
+        fix_left(&mut node.left);
+        node.update();
+        *link = Some(node);
+        return false;
+    }
+    let sibling = node
+        .right
+        .as_mut()
+        .expect("sibling of short subtree exists");
+    if !is_red(&sibling.left) && !is_red(&sibling.right) {
+        sibling.red = true;
+        let short = !node.red;
+        node.red = false;
+        *link = Some(node);
+        return short;
+    }
+    if !is_red(&sibling.right) {
+        let sibling = node.right.take().expect("sibling exists");
+        let mut sibling = Bst::rotate_right(sibling);
+        sibling.red = false;
+        set_red(&mut sibling.right);
+        *node.right = Some(sibling);
+    }
+    let red = node.red;
+    node = Bst::rotate_left(node);
+    node.red = red;
+    set_black(&mut node.left);
+    set_black(&mut node.right);
+    *link = Some(node);
+    false
+}
+
+/// Mirror image of fix_left, for when the right subtree became short.
+fn fix_right<E, A: Monoid<E>>(link: &mut Option<Box<Bst<E, A>>>) -> bool {
+    let mut node = link.take().expect("repaired subtree is not empty");
+    if is_red(&node.right) {
+        set_black(&mut node.right);
+        *link = Some(node);
+        return false;
+    }
+    if is_red(&node.left) {
+        node = Bst::rotate_right(node);
+        node.red = false;
+        set_red(&mut node.right);
+        fix_right(&mut node.right);
+        node.update();
+        *link = Some(node);
+        return false;
+    }
+    let sibling = node.left.as_mut().expect("sibling of short subtree exists");
+    if !is_red(&sibling.left) && !is_red(&sibling.right) {
+        sibling.red = true;
+        let short = !node.red;
+        node.red = false;
+        *link = Some(node);
+        return short;
+    }
+    if !is_red(&sibling.left) {
+        let sibling = node.left.take().expect("sibling exists");
+        let mut sibling = Bst::rotate_left(sibling);
+        sibling.red = false;
+        set_red(&mut sibling.left);
+        *node.left = Some(sibling);
+    }
+    let red = node.red;
+    node = Bst::rotate_right(node);
+    node.red = red;
+    set_black(&mut node.left);
+    set_black(&mut node.right);
+    *link = Some(node);
+    false
+}
+
+/// Colors the root of [link] black, if there is one
+fn set_black<E, A>(link: &mut Option<Box<Bst<E, A>>>) {
+    if let Some(node) = link.as_mut() {
+        node.red = false;
+    }
+}
+
+/// Colors the root of [link] red, if there is one
+fn set_red<E, A>(link: &mut Option<Box<Bst<E, A>>>) {
+    if let Some(node) = link.as_mut() {
+        node.red = true;
+    }
+}
+
+/// Checks the red-black invariants below [link], panicking on a red node
+/// with a red child or on subtrees with different black heights.
+/// Returns the black height of the subtree (counting empty subtrees as black).
+pub(crate) fn check<E, A>(link: &Option<Box<Bst<E, A>>>) -> usize {
+    match link {
+        None => 1,
+        Some(node) => {
+            assert!(
+                !(node.red && (is_red(&node.left) || is_red(&node.right))),
+                "red node has a red child"
+            );
+            let left = check(&node.left);
+            let right = check(&node.right);
+            assert_eq!(left, right, "subtrees have different black heights");
+            left + usize::from(!node.red)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::test_util::Rng;
+    use crate::{RbMap, RbMultiset, RbSet};
+
+    #[test]
+    fn red_black_matches_btree_set() {
+        let mut rng = Rng(0x5eed);
+        let mut set = RbSet::new();
+        let mut expected = BTreeSet::new();
+        for _ in 0..20_000 {
+            let key = rng.below(1_000);
+            match rng.below(8) {
+                0..=3 => assert_eq!(set.insert(key), expected.insert(key)),
+                4 | 5 => assert_eq!(set.remove(&key), expected.remove(&key)),
+                6 => assert_eq!(set.pop_first(), expected.pop_first()),
+                _ => assert_eq!(set.pop_last(), expected.pop_last()),
+            }
+            set.check_invariants();
+            assert_eq!(set.len(), expected.len());
+        }
+        assert!(set.iter().eq(expected.iter()));
+
+        // Sorted input, then draining from the middle outwards
+        let mut set: RbSet<u32> = RbSet::new();
+        for value in 0..1_000 {
+            set.insert(value);
+            set.check_invariants();
+        }
+        for offset in 0..500 {
+            assert!(set.remove(&(500 + offset)));
+            assert!(set.remove(&(499 - offset)));
+            set.check_invariants();
+        }
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn red_black_map_and_multiset_stay_valid() {
+        let mut rng = Rng(0xb1ac);
+        let mut map = RbMap::new();
+        let mut expected = BTreeMap::new();
+        let mut multiset = RbMultiset::new();
+        for step in 0..10_000 {
+            let key = rng.below(300);
+            if rng.below(3) > 0 {
+                assert_eq!(map.insert(key, step), expected.insert(key, step));
+                multiset.insert(key);
+            } else {
+                assert_eq!(map.remove(&key), expected.remove(&key));
+                multiset.remove_one(&key);
+            }
+            map.check_invariants();
+            multiset.check_invariants();
+        }
+        assert!(map.iter().eq(expected.iter()));
+    }
+}
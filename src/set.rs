@@ -0,0 +1,1194 @@
+use std::borrow::Borrow;
+use std::cmp;
+use std::fmt;
+use std::iter;
+use std::marker::PhantomData;
+use std::ops;
+
+use crate::{
+    rb, AsF64, Avl, Balance, Bst, BstIter, Compare, IntoIter, Monoid, Natural, RedBlack, Unbalanced,
+};
+
+/// An ordered set of elements of type E, backed by a binary search tree that
+/// is kept balanced by the strategy B and ordered by the comparator C. Unlike a
+/// bare Bst, a TreeSet owns an optional root and so may be empty. Each node
+/// caches the aggregate A of its subtree (nothing, by default; see Monoid).
+#[derive(Clone)]
+pub struct TreeSet<E, B, C = Natural, A = ()> {
+    /// Root node of the tree, or None if the set is empty
+    pub(crate) root: Option<Box<Bst<E, A>>>,
+    balance: PhantomData<B>,
+    /// Ordering of the elements
+    cmp: C,
+}
+
+/// A set backed by a plain, never-rebalanced binary search tree
+pub type BstSet<E, C = Natural, A = ()> = TreeSet<E, Unbalanced, C, A>;
+
+/// A set backed by an AVL tree
+pub type AvlSet<E, C = Natural, A = ()> = TreeSet<E, Avl, C, A>;
+
+/// A set backed by a red-black tree
+pub type RbSet<E, C = Natural, A = ()> = TreeSet<E, RedBlack, C, A>;
+
+/// An empty set is the default
+impl<E, B, C: Default, A: Monoid<E>> Default for TreeSet<E, B, C, A> {
+    fn default() -> Self {
+        Self::from_root(None, C::default())
+    }
+}
+
+/// Print space-separated in-order traversal of the set (nothing if empty)
+impl<E: fmt::Display, B, C, A> fmt::Display for TreeSet<E, B, C, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.root {
+            Some(root) => write!(f, "{}", root),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Formats the set as its elements, in order, e.g. `{1, 2, 3}`, as for Bst
+impl<E: fmt::Debug, B, C, A> fmt::Debug for TreeSet<E, B, C, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(BstIter::from_root(self.root.as_deref()))
+            .finish()
+    }
+}
+
+/// Consumes the set, yielding its elements in order
+impl<E, B, C, A> IntoIterator for TreeSet<E, B, C, A> {
+    type Item = E;
+    type IntoIter = IntoIter<E, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::from_root(self.root)
+    }
+}
+
+/// Allows a borrowed set to be used in, e.g. for loops
+impl<'a, E, B, C, A: Monoid<E>> IntoIterator for &'a TreeSet<E, B, C, A> {
+    type Item = &'a E;
+    type IntoIter = BstIter<'a, E, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Collects values into a set by sorting them and building a balanced tree,
+/// which takes O(n log n) time. Of equal values, the first is kept.
+impl<E, B, C: Compare<E> + Default, A: Monoid<E>> FromIterator<E> for TreeSet<E, B, C, A> {
+    fn from_iter<I: IntoIterator<Item = E>>(iter: I) -> Self {
+        let cmp = C::default();
+        let mut values: Vec<E> = iter.into_iter().collect();
+        values.sort_by(|a, b| cmp.compare(a, b));
+        Self::from_sorted(values, cmp)
+    }
+}
+
+/// Inserts each value in turn, as for insert
+impl<E, B: Balance, C: Compare<E>, A: Monoid<E>> Extend<E> for TreeSet<E, B, C, A> {
+    fn extend<I: IntoIterator<Item = E>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+/// Constructor for sets using the natural ordering of their elements
+impl<E: cmp::Ord, B> TreeSet<E, B, Natural> {
+    /// Makes a new, empty set.
+    pub fn new() -> Self {
+        Self::with_comparator(Natural)
+    }
+
+    /// Makes a set of [values], which must be in ascending order, in O(n) time
+    /// and with every level of the tree full except possibly the last. Of a run
+    /// of equal values, only the first is kept.
+    /// Panics if the values are not in order.
+    pub fn from_sorted_vec(values: Vec<E>) -> Self {
+        Self::from_sorted(values, Natural)
+    }
+
+    /// Makes a set of the values yielded by [iter], as for from_sorted_vec.
+    pub fn from_sorted_iter<I: IntoIterator<Item = E>>(iter: I) -> Self {
+        Self::from_sorted_vec(iter.into_iter().collect())
+    }
+}
+
+/// Constructor for sets that cache no aggregate, whatever their ordering
+impl<E, B, C> TreeSet<E, B, C> {
+    /// Makes a new, empty set ordered by [cmp], e.g.
+    /// `BstSet::with_comparator(|a: &f64, b: &f64| a.total_cmp(b))`.
+    pub fn with_comparator(cmp: C) -> Self {
+        Self::from_root(None, cmp)
+    }
+}
+
+/// Methods for TreeSet that do not compare elements
+impl<E, B, C, A: Monoid<E>> TreeSet<E, B, C, A> {
+    /// Makes a set ordered by [cmp] of the tree at [root]
+    fn from_root(root: Option<Box<Bst<E, A>>>, cmp: C) -> Self {
+        Self {
+            root,
+            balance: PhantomData,
+            cmp,
+        }
+    }
+
+    /// Makes a set ordered by [cmp] of [values], as for from_sorted_vec.
+    fn from_sorted(mut values: Vec<E>, cmp: C) -> Self
+    where
+        C: Compare<E>,
+    {
+        // dedup_by passes each value along with the last one kept before it
+        values.dedup_by(|value, kept| match cmp.compare(kept, value) {
+            cmp::Ordering::Less => false,
+            cmp::Ordering::Equal => true,
+            cmp::Ordering::Greater => panic!("values are not in ascending order"),
+        });
+        let len = values.len();
+        Self::from_root(Bst::build_balanced(&mut values.into_iter(), len), cmp)
+    }
+
+    /// Returns true if the set contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Returns the number of elements in the set.
+    pub fn len(&self) -> usize {
+        Bst::size_of(&self.root)
+    }
+
+    /// Gets the iterator for this set, starting at the least element.
+    pub fn iter(&self) -> BstIter<'_, E, A> {
+        BstIter::from_root(self.root.as_deref())
+    }
+
+    /// Returns the least element of the set, or None if it is empty.
+    pub fn first(&self) -> Option<&E> {
+        Bst::first_of(self.root.as_deref())
+    }
+
+    /// Returns the [k]th least element of the set, counting from 0, or None if
+    /// the set has no more than [k] elements.
+    pub fn select(&self, k: usize) -> Option<&E> {
+        Bst::get_at(self.root.as_deref(), k)
+    }
+
+    /// Returns the greatest element of the set, or None if it is empty.
+    pub fn last(&self) -> Option<&E> {
+        Bst::last_of(self.root.as_deref())
+    }
+
+    /// Keeps only the elements for which [f] returns true, visiting them in
+    /// order. The kept elements are rebuilt into a balanced tree, so this takes
+    /// O(n) time however many are removed.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&E) -> bool,
+    {
+        let kept: Vec<E> = IntoIter::from_root(self.root.take())
+            .filter(|value| f(value))
+            .collect();
+        let len = kept.len();
+        self.root = Bst::build_balanced(&mut kept.into_iter(), len);
+    }
+
+    /// Removes every element from the set, returning an iterator that yields
+    /// them in order. The set is empty as soon as this returns, even if the
+    /// iterator is not consumed.
+    pub fn drain(&mut self) -> IntoIter<E, A> {
+        IntoIter::from_root(self.root.take())
+    }
+
+    /// Returns the aggregate of all the elements of the set (the identity if it
+    /// is empty), which is cached at the root of its tree, in O(1) time.
+    pub fn aggregate(&self) -> A {
+        Bst::aggregate_of(&self.root)
+    }
+
+    /// Converts the set into one whose tree has the same shape but caches the
+    /// aggregate A2 in every node instead, e.g.
+    /// `AvlSet::new().with_aggregate::<Sum<i64>>()`, in O(n) time.
+    pub fn with_aggregate<A2: Monoid<E>>(self) -> TreeSet<E, B, C, A2> {
+        TreeSet::from_root(Bst::reaggregate(self.root), self.cmp)
+    }
+}
+
+/// Methods for TreeSet, parameterized over its element type, balancing strategy,
+/// comparator and aggregate.
+impl<E, B: Balance, C: Compare<E>, A: Monoid<E>> TreeSet<E, B, C, A> {
+    /// Inserts the value into the set in the proper (sorted) position.
+    /// Returns true if inserted, false if already present.
+    pub fn insert(&mut self, new_val: E) -> bool {
+        B::insert(
+            &mut self.root,
+            new_val,
+            |new_val, node| self.cmp.compare(new_val, &node.value),
+            |_, _| (),
+        )
+        .is_none()
+    }
+
+    /// Returns a reference to the value in the set equal to [key], if any.
+    /// The key may be any borrowed form of the element type that the
+    /// comparator can also order (with Natural, any such type that is Ord).
+    pub fn get<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::get_by(self.root.as_deref(), |v| self.cmp.compare(key, v.borrow()))
+    }
+
+    /// Returns true if the set contains a value equal to [key].
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Gets an iterator over the elements of the set that lie within [range],
+    /// in order, e.g. `set.range(1000..2000)`.
+    pub fn range<Q, R>(&self, range: R) -> BstIter<'_, E, A>
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+        R: ops::RangeBounds<Q>,
+    {
+        BstIter::from_range(self.root.as_deref(), &range, |bound, v| {
+            self.cmp.compare(bound, v.borrow())
+        })
+    }
+
+    /// Returns the aggregate of the elements of the set that lie within
+    /// [range], e.g. `set.aggregate_range(1000..2000)`, in O(height) time: it is
+    /// combined from the aggregates cached along the paths to the ends of the
+    /// range, without visiting the elements in between.
+    pub fn aggregate_range<Q, R>(&self, range: R) -> A
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+        R: ops::RangeBounds<Q>,
+    {
+        Bst::aggregate_range_by(self.root.as_deref(), &range, |bound, v| {
+            self.cmp.compare(bound, v.borrow())
+        })
+    }
+
+    /// Returns the greatest element of the set less than or equal to [key], if any.
+    pub fn floor<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::floor_by(
+            self.root.as_deref(),
+            |v| self.cmp.compare(key, v.borrow()),
+            true,
+        )
+    }
+
+    /// Returns the number of elements of the set less than [key], whether or
+    /// not [key] itself is present.
+    pub fn rank<Q>(&self, key: &Q) -> usize
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::rank_by(self.root.as_deref(), |v| self.cmp.compare(key, v.borrow())).0
+    }
+
+    /// Returns the least element of the set greater than or equal to [key], if any.
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::ceiling_by(
+            self.root.as_deref(),
+            |v| self.cmp.compare(key, v.borrow()),
+            true,
+        )
+    }
+
+    /// Returns the greatest element of the set strictly less than [key], if any.
+    pub fn predecessor<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::floor_by(
+            self.root.as_deref(),
+            |v| self.cmp.compare(key, v.borrow()),
+            false,
+        )
+    }
+
+    /// Returns the least element of the set strictly greater than [key], if any.
+    pub fn successor<Q>(&self, key: &Q) -> Option<&E>
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        Bst::ceiling_by(
+            self.root.as_deref(),
+            |v| self.cmp.compare(key, v.borrow()),
+            false,
+        )
+    }
+
+    /// Removes the value equal to [key] from the set, returning it if it was present.
+    pub fn take<Q>(&mut self, key: &Q) -> Option<E>
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        B::take(&mut self.root, |node| {
+            self.cmp.compare(key, node.value.borrow())
+        })
+    }
+
+    /// Removes and returns the least element of the set, or None if it is empty.
+    pub fn pop_first(&mut self) -> Option<E> {
+        B::take_first(&mut self.root)
+    }
+
+    /// Removes and returns the greatest element of the set, or None if it is empty.
+    pub fn pop_last(&mut self) -> Option<E> {
+        B::take_last(&mut self.root)
+    }
+
+    /// Gets an iterator that visits the elements of the set in order, removing
+    /// and yielding those for which [pred] returns true. Elements are only
+    /// visited (and removed) as the iterator advances, so dropping it early
+    /// leaves the rest of the set untouched. Each removal rebalances the tree
+    /// and takes O(height) time.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, E, B, C, F, A>
+    where
+        F: FnMut(&E) -> bool,
+    {
+        ExtractIf {
+            set: self,
+            index: 0,
+            pred,
+        }
+    }
+
+    /// Removes the value equal to [key] from the set.
+    /// Returns true if removed, false if not present.
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+    {
+        self.take(key).is_some()
+    }
+
+    /// Moves the elements of the set greater than or equal to [key] into a new
+    /// set with the same comparator, which is returned. Takes O(height) time in
+    /// the balanced modes.
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        E: Borrow<Q>,
+        C: Compare<Q> + Clone,
+        Q: ?Sized,
+    {
+        let after = B::split(&mut self.root, |v| self.cmp.compare(key, v.borrow()));
+        Self::from_root(after, self.cmp.clone())
+    }
+
+    /// Removes every element of the set that lies within [range], e.g.
+    /// `set.remove_range(1000..2000)`, returning how many were removed. Takes
+    /// O(height + k) time in the balanced modes, to remove k elements.
+    pub fn remove_range<Q, R>(&mut self, range: R) -> usize
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+        R: ops::RangeBounds<Q>,
+    {
+        let removed = B::take_range(&mut self.root, &range, |bound, v| {
+            self.cmp.compare(bound, v.borrow())
+        });
+        Bst::size_of(&removed)
+    }
+
+    /// Moves every element of [other] into this set, leaving [other] empty.
+    /// Elements of [other] equal to one already in this set are dropped. If every
+    /// element of one set precedes every element of the other, the trees are
+    /// joined in O(height) time in the balanced modes; otherwise they are merged
+    /// in O(n + m) time.
+    pub fn append(&mut self, other: &mut Self) {
+        B::append(
+            &mut self.root,
+            &mut other.root,
+            |a, b| self.cmp.compare(a, b),
+            |ours, _| ours,
+        );
+    }
+
+    /// Applies [f] to the element of the set equal to [key], in place. If that
+    /// changes where the element belongs in the order, it is then removed and
+    /// reinserted; should it now equal another element of the set, it is dropped,
+    /// as for insert. Returns true if an element equal to [key] was found.
+    pub fn update<Q, F>(&mut self, key: &Q, f: F) -> bool
+    where
+        E: Borrow<Q>,
+        C: Compare<Q>,
+        Q: ?Sized,
+        F: FnOnce(&mut E),
+    {
+        let (index, found) =
+            Bst::rank_by(self.root.as_deref(), |v| self.cmp.compare(key, v.borrow()));
+        if !found {
+            return false;
+        }
+        Bst::modify_at(&mut self.root, index, f).expect("found element exists");
+
+        let root = self.root.as_deref();
+        let value = Bst::get_at(root, index).expect("found element exists");
+        let after_prev = index
+            .checked_sub(1)
+            .and_then(|prev| Bst::get_at(root, prev))
+            .is_none_or(|prev| self.cmp.compare(prev, value) == cmp::Ordering::Less);
+        let before_next = Bst::get_at(root, index + 1)
+            .is_none_or(|next| self.cmp.compare(value, next) == cmp::Ordering::Less);
+        if !(after_prev && before_next) {
+            let value =
+                B::take(&mut self.root, Bst::locate_at(index)).expect("found element exists");
+            B::insert(
+                &mut self.root,
+                value,
+                |value, node| self.cmp.compare(value, &node.value),
+                |_, _| (),
+            );
+        }
+        true
+    }
+
+    /// Gets an iterator over the elements of this set or [other] (or both), in
+    /// order. Runs in O(n + m) time in total.
+    pub fn union<'a>(&'a self, other: &'a Self) -> Union<'a, E, C, A> {
+        Union {
+            merge: Merge::new(self, other),
+        }
+    }
+
+    /// Gets an iterator over the elements of both this set and [other], in
+    /// order, taken from this set. Runs in O(n + m) time in total.
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> Intersection<'a, E, C, A> {
+        Intersection {
+            merge: Merge::new(self, other),
+        }
+    }
+
+    /// Gets an iterator over the elements of this set that are not in [other],
+    /// in order. Runs in O(n + m) time in total.
+    pub fn difference<'a>(&'a self, other: &'a Self) -> Difference<'a, E, C, A> {
+        Difference {
+            merge: Merge::new(self, other),
+        }
+    }
+
+    /// Gets an iterator over the elements of exactly one of this set and
+    /// [other], in order. Runs in O(n + m) time in total.
+    pub fn symmetric_difference<'a>(&'a self, other: &'a Self) -> SymmetricDifference<'a, E, C, A> {
+        SymmetricDifference {
+            merge: Merge::new(self, other),
+        }
+    }
+
+    /// Returns true if every element of this set is also in [other].
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.len() <= other.len() && self.difference(other).next().is_none()
+    }
+
+    /// Returns true if every element of [other] is also in this set.
+    pub fn is_superset(&self, other: &Self) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Returns true if this set and [other] have no elements in common.
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        self.intersection(other).next().is_none()
+    }
+}
+
+/// Methods specific to red-black sets
+impl<E, C, A> TreeSet<E, RedBlack, C, A> {
+    /// Checks that the tree satisfies the red-black invariants: a black root,
+    /// no red node with a red child, and equal black heights on every path.
+    /// Panics if any is violated. Takes O(n) time, as it visits every node.
+    /// Meant for tests and debugging, so hidden from the documentation.
+    #[doc(hidden)]
+    pub fn check_invariants(&self) {
+        assert!(
+            self.root.as_ref().is_none_or(|root| !root.red),
+            "root is red"
+        );
+        rb::check(&self.root);
+    }
+}
+
+/// Folds over the elements of a TreeSet, in order, as for Bst. Folding an
+/// empty set gives the identity, e.g. a sum of 0 and a product of 1, and no
+/// min, max or mean.
+impl<E, B, C, A: Monoid<E>> TreeSet<E, B, C, A> {
+    /// Sums the elements of the set.
+    pub fn sum<'a>(&'a self) -> E
+    where
+        E: iter::Sum<&'a E>,
+    {
+        self.iter().sum()
+    }
+
+    /// Multiplies the elements of the set.
+    pub fn product<'a>(&'a self) -> E
+    where
+        E: iter::Product<&'a E>,
+    {
+        self.iter().product()
+    }
+
+    /// Folds the elements of the set in order into [identity], applying
+    /// [combine] to the running total and each element in turn.
+    pub fn sum_with<T, F>(&self, identity: T, combine: F) -> T
+    where
+        F: FnMut(T, &E) -> T,
+    {
+        self.iter().fold(identity, combine)
+    }
+
+    /// Returns the element with the least key under [f]; of several, the
+    /// first in order.
+    pub fn min_by_key<K: cmp::Ord, F: FnMut(&E) -> K>(&self, mut f: F) -> Option<&E> {
+        self.iter().min_by_key(|value| f(value))
+    }
+
+    /// Returns the element with the greatest key under [f]; of several, the
+    /// last in order.
+    pub fn max_by_key<K: cmp::Ord, F: FnMut(&E) -> K>(&self, mut f: F) -> Option<&E> {
+        self.iter().max_by_key(|value| f(value))
+    }
+
+    /// Returns the arithmetic mean of the elements as f64, summed as exactly
+    /// as their type allows; see AsF64::mean_of.
+    pub fn mean(&self) -> Option<f64>
+    where
+        E: AsF64,
+    {
+        if self.is_empty() {
+            return None;
+        }
+        Some(E::mean_of(self.iter()))
+    }
+}
+
+/// Union of two sets as a new set, built from the merged elements in O(n + m)
+impl<E, B, C, A> ops::BitOr<&TreeSet<E, B, C, A>> for &TreeSet<E, B, C, A>
+where
+    E: Clone,
+    B: Balance,
+    C: Compare<E> + Clone,
+    A: Monoid<E>,
+{
+    type Output = TreeSet<E, B, C, A>;
+
+    fn bitor(self, rhs: &TreeSet<E, B, C, A>) -> Self::Output {
+        TreeSet::from_sorted(self.union(rhs).cloned().collect(), self.cmp.clone())
+    }
+}
+
+/// Intersection of two sets as a new set, built from the merged elements in O(n + m)
+impl<E, B, C, A> ops::BitAnd<&TreeSet<E, B, C, A>> for &TreeSet<E, B, C, A>
+where
+    E: Clone,
+    B: Balance,
+    C: Compare<E> + Clone,
+    A: Monoid<E>,
+{
+    type Output = TreeSet<E, B, C, A>;
+
+    fn bitand(self, rhs: &TreeSet<E, B, C, A>) -> Self::Output {
+        TreeSet::from_sorted(self.intersection(rhs).cloned().collect(), self.cmp.clone())
+    }
+}
+
+/// Difference of two sets as a new set, built from the merged elements in O(n + m)
+impl<E, B, C, A> ops::Sub<&TreeSet<E, B, C, A>> for &TreeSet<E, B, C, A>
+where
+    E: Clone,
+    B: Balance,
+    C: Compare<E> + Clone,
+    A: Monoid<E>,
+{
+    type Output = TreeSet<E, B, C, A>;
+
+    fn sub(self, rhs: &TreeSet<E, B, C, A>) -> Self::Output {
+        TreeSet::from_sorted(self.difference(rhs).cloned().collect(), self.cmp.clone())
+    }
+}
+
+/// Symmetric difference of two sets as a new set, built from the merged
+/// elements in O(n + m)
+impl<E, B, C, A> ops::BitXor<&TreeSet<E, B, C, A>> for &TreeSet<E, B, C, A>
+where
+    E: Clone,
+    B: Balance,
+    C: Compare<E> + Clone,
+    A: Monoid<E>,
+{
+    type Output = TreeSet<E, B, C, A>;
+
+    fn bitxor(self, rhs: &TreeSet<E, B, C, A>) -> Self::Output {
+        TreeSet::from_sorted(
+            self.symmetric_difference(rhs).cloned().collect(),
+            self.cmp.clone(),
+        )
+    }
+}
+
+/// Merge walk over two sets ordered by the same comparator, shared by the set
+/// algebra iterators
+#[derive(Debug)]
+struct Merge<'a, E, C, A> {
+    a: iter::Peekable<BstIter<'a, E, A>>,
+    b: iter::Peekable<BstIter<'a, E, A>>,
+    cmp: &'a C,
+}
+
+impl<'a, E, C: Compare<E>, A> Merge<'a, E, C, A> {
+    /// Starts a merge walk over the elements of [a] and [b], using the
+    /// comparator of [a]
+    fn new<B>(a: &'a TreeSet<E, B, C, A>, b: &'a TreeSet<E, B, C, A>) -> Self {
+        Self {
+            a: BstIter::from_root(a.root.as_deref()).peekable(),
+            b: BstIter::from_root(b.root.as_deref()).peekable(),
+            cmp: &a.cmp,
+        }
+    }
+
+    /// Advances past the least element not yet seen, returning it from each
+    /// set that contains it, or None once both sets are exhausted
+    fn next(&mut self) -> Option<(Option<&'a E>, Option<&'a E>)> {
+        let order = match (self.a.peek(), self.b.peek()) {
+            (None, None) => return None,
+            (Some(_), None) => cmp::Ordering::Less,
+            (None, Some(_)) => cmp::Ordering::Greater,
+            (Some(a), Some(b)) => self.cmp.compare(a, b),
+        };
+        Some(match order {
+            cmp::Ordering::Less => (self.a.next(), None),
+            cmp::Ordering::Greater => (None, self.b.next()),
+            cmp::Ordering::Equal => (self.a.next(), self.b.next()),
+        })
+    }
+
+    /// Numbers of elements left in each set
+    fn lens(&self) -> (usize, usize) {
+        (self.a.len(), self.b.len())
+    }
+}
+
+/// Iterator over the union of two TreeSets, in order
+#[derive(Debug)]
+pub struct Union<'a, E, C, A = ()> {
+    merge: Merge<'a, E, C, A>,
+}
+
+impl<'a, E, C: Compare<E>, A> Iterator for Union<'a, E, C, A> {
+    type Item = &'a E;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (a, b) = self.merge.next()?;
+        a.or(b)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = self.merge.lens();
+        (cmp::max(a, b), Some(a + b))
+    }
+}
+
+/// Iterator over the intersection of two TreeSets, in order
+#[derive(Debug)]
+pub struct Intersection<'a, E, C, A = ()> {
+    merge: Merge<'a, E, C, A>,
+}
+
+impl<'a, E, C: Compare<E>, A> Iterator for Intersection<'a, E, C, A> {
+    type Item = &'a E;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let (Some(a), Some(_)) = self.merge.next()? {
+                return Some(a);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = self.merge.lens();
+        (0, Some(cmp::min(a, b)))
+    }
+}
+
+/// Iterator over the difference of two TreeSets, in order
+#[derive(Debug)]
+pub struct Difference<'a, E, C, A = ()> {
+    merge: Merge<'a, E, C, A>,
+}
+
+impl<'a, E, C: Compare<E>, A> Iterator for Difference<'a, E, C, A> {
+    type Item = &'a E;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let (Some(a), None) = self.merge.next()? {
+                return Some(a);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = self.merge.lens();
+        (a.saturating_sub(b), Some(a))
+    }
+}
+
+/// Iterator over the symmetric difference of two TreeSets, in order
+#[derive(Debug)]
+pub struct SymmetricDifference<'a, E, C, A = ()> {
+    merge: Merge<'a, E, C, A>,
+}
+
+impl<'a, E, C: Compare<E>, A> Iterator for SymmetricDifference<'a, E, C, A> {
+    type Item = &'a E;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.merge.next()? {
+                (Some(a), None) => return Some(a),
+                (None, Some(b)) => return Some(b),
+                _ => {}
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = self.merge.lens();
+        (0, Some(a + b))
+    }
+}
+
+/// Iterator that removes and yields the elements of a TreeSet that match a
+/// predicate, in order
+#[derive(Debug)]
+pub struct ExtractIf<'a, E, B, C, F, A = ()> {
+    set: &'a mut TreeSet<E, B, C, A>,
+    /// In-order index of the next element to visit, which is also the number
+    /// of elements visited and kept so far
+    index: usize,
+    pred: F,
+}
+
+impl<'a, E, B, C, F, A> Iterator for ExtractIf<'a, E, B, C, F, A>
+where
+    B: Balance,
+    F: FnMut(&E) -> bool,
+    A: Monoid<E>,
+{
+    type Item = E;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let value = Bst::get_at(self.set.root.as_deref(), self.index)?;
+            if (self.pred)(value) {
+                return B::take(&mut self.set.root, Bst::locate_at(self.index));
+            }
+            self.index += 1;
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.set.len() - self.index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::test_util::{Checked, Keyed, Rng};
+    use crate::TreeMap;
+
+    #[test]
+    fn empty_set_grows_from_nothing() {
+        let mut set = BstSet::new();
+        assert!(set.is_empty());
+        assert_eq!(set.len(), 0);
+        assert_eq!(set.iter().next(), None);
+        assert_eq!(set.to_string(), "");
+        assert_eq!(set.sum(), 0);
+
+        assert!(set.insert(5));
+        assert!(set.insert(3));
+        assert!(!set.insert(5));
+        assert!(!set.is_empty());
+        assert_eq!(set.len(), 2);
+        assert!(set.iter().copied().eq([3, 5]));
+        assert_eq!(set.to_string(), "3 5");
+        assert_eq!(set.sum(), 8);
+    }
+
+    #[test]
+    fn set_take_down_to_empty() {
+        let mut set = BstSet::new();
+        for value in [50, 30, 70, 20, 40, 60, 80, 35, 65] {
+            set.insert(value);
+        }
+        assert_eq!(set.take(&30), Some(30));
+        assert_eq!(set.take(&20), Some(20));
+        assert_eq!(set.take(&60), Some(60));
+        assert_eq!(set.take(&50), Some(50));
+        assert!(set.iter().copied().eq([35, 40, 65, 70, 80]));
+        assert_eq!(set.len(), 5);
+        for value in [65, 35, 80, 40] {
+            assert!(set.remove(&value));
+        }
+        assert_eq!(set.take(&70), Some(70));
+        assert!(set.is_empty());
+        assert_eq!(set.take(&70), None);
+        assert_eq!(set.len(), 0);
+    }
+
+    /// Takes values from both ends of a set of B, checking its first and last
+    /// values against a BTreeSet after each step
+    fn check_ends<B: Balance>() {
+        let mut rng = Rng(0xe2d5);
+        let mut set: TreeSet<u64, B> = TreeSet::new();
+        let mut expected = BTreeSet::new();
+        assert_eq!(set.first(), None);
+        assert_eq!(set.pop_last(), None);
+        for _ in 0..3_000 {
+            match rng.below(4) {
+                0 => assert_eq!(set.pop_first(), expected.pop_first()),
+                1 => assert_eq!(set.pop_last(), expected.pop_last()),
+                _ => {
+                    let value = rng.below(500);
+                    assert_eq!(set.insert(value), expected.insert(value));
+                }
+            }
+            assert_eq!(set.first(), expected.first());
+            assert_eq!(set.last(), expected.last());
+            assert_eq!(set.len(), expected.len());
+        }
+        while let Some(value) = set.pop_first() {
+            assert_eq!(Some(value), expected.pop_first());
+        }
+        assert!(expected.is_empty());
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn ends_match_btree_set() {
+        check_ends::<Unbalanced>();
+        check_ends::<Avl>();
+        check_ends::<RedBlack>();
+
+        let mut tree = Bst::new(4);
+        assert_eq!((tree.first(), tree.last()), (&4, &4));
+        for value in [2, 7, 1, 9, 3] {
+            tree.insert(value);
+        }
+        assert_eq!((tree.first(), tree.last()), (&1, &9));
+    }
+
+    #[test]
+    fn set_algebra_matches_btree_set() {
+        let mut rng = Rng(0xa1eb);
+        for round in 0..200 {
+            let len = |rng: &mut Rng| rng.below(if round < 20 { 3 } else { 60 });
+            let (len_a, len_b) = (len(&mut rng), len(&mut rng));
+            let a: BTreeSet<u64> = (0..len_a).map(|_| rng.below(80)).collect();
+            let b: BTreeSet<u64> = (0..len_b).map(|_| rng.below(80)).collect();
+            let (x, y): (AvlSet<u64>, AvlSet<u64>) =
+                (a.iter().copied().collect(), b.iter().copied().collect());
+
+            assert!(x.union(&y).eq(a.union(&b)));
+            assert!(x.intersection(&y).eq(a.intersection(&b)));
+            assert!(x.difference(&y).eq(a.difference(&b)));
+            assert!(x.symmetric_difference(&y).eq(a.symmetric_difference(&b)));
+            assert!((&x | &y).into_iter().eq(&a | &b));
+            assert!((&x & &y).into_iter().eq(&a & &b));
+            assert!((&x - &y).into_iter().eq(&a - &b));
+            assert!((&x ^ &y).into_iter().eq(&a ^ &b));
+            assert_eq!((&x | &y).len(), (&a | &b).len());
+            assert_eq!(x.is_subset(&y), a.is_subset(&b));
+            assert_eq!(x.is_superset(&y), a.is_superset(&b));
+            assert_eq!(x.is_disjoint(&y), a.is_disjoint(&b));
+
+            let (low, high) = x.union(&y).size_hint();
+            assert!(low <= a.union(&b).count() && Some(a.union(&b).count()) <= high);
+            let (low, high) = x.difference(&y).size_hint();
+            assert!(low <= a.difference(&b).count() && Some(a.difference(&b).count()) <= high);
+        }
+
+        let small: RbSet<i32> = [2, 4].into_iter().collect();
+        let large: RbSet<i32> = (0..6).collect();
+        let empty = RbSet::new();
+        assert!(small.is_subset(&large) && large.is_superset(&small));
+        assert!(!large.is_subset(&small));
+        assert!(empty.is_subset(&small) && empty.is_disjoint(&small));
+        assert!(small.is_subset(&small) && !small.is_disjoint(&small));
+        assert!((&large - &small).iter().copied().eq([0, 1, 3, 5]));
+        assert!((&small ^ &empty).iter().copied().eq([2, 4]));
+    }
+
+    /// Splits sets of B at many keys and appends them back, checking against a
+    /// BTreeSet
+    fn check_split_and_append<B: Checked>() {
+        let mut rng = Rng(0x5b11);
+        let values: BTreeSet<u64> = (0..300).map(|_| rng.below(1_000)).collect();
+        let set: TreeSet<u64, B> = values.iter().copied().collect();
+        for key in (0..1_050).step_by(25) {
+            let mut before = set.clone();
+            let mut after = before.split_off(&key);
+            let mut expected = values.clone();
+            let expected_after = expected.split_off(&key);
+            assert!(before.iter().eq(expected.iter()));
+            assert!(after.iter().eq(expected_after.iter()));
+            assert_eq!(before.len(), expected.len());
+            assert_eq!(after.len(), expected_after.len());
+            B::check(&before.root);
+            B::check(&after.root);
+
+            // Joined back in either order, as every value of one precedes the other
+            let mut joined = before.clone();
+            joined.append(&mut after.clone());
+            assert!(joined.iter().eq(values.iter()));
+            B::check(&joined.root);
+            after.append(&mut before);
+            assert!(after.iter().eq(values.iter()));
+            assert_eq!(after.len(), values.len());
+            assert!(before.is_empty());
+            B::check(&after.root);
+        }
+
+        // Interleaved sets are merged
+        for (len_a, len_b) in [(0, 10), (10, 0), (1, 1), (50, 200), (200, 7)] {
+            let a: BTreeSet<u64> = (0..len_a).map(|_| rng.below(300)).collect();
+            let b: BTreeSet<u64> = (0..len_b).map(|_| rng.below(300)).collect();
+            let mut x: TreeSet<u64, B> = a.iter().copied().collect();
+            let mut y: TreeSet<u64, B> = b.iter().copied().collect();
+            x.append(&mut y);
+            assert!(x.iter().eq(a.union(&b)));
+            assert_eq!(x.len(), a.union(&b).count());
+            assert!(y.is_empty());
+            B::check(&x.root);
+        }
+
+        // A map keeps the appended value for keys in both maps
+        let mut ours: TreeMap<u32, char, B> = [(1, 'a'), (3, 'c'), (5, 'e')].into_iter().collect();
+        let mut theirs: TreeMap<u32, char, B> = [(3, 'C'), (4, 'D')].into_iter().collect();
+        ours.append(&mut theirs);
+        assert!(ours
+            .iter()
+            .eq([(&1, &'a'), (&3, &'C'), (&4, &'D'), (&5, &'e')]));
+        let rest = ours.split_off(&4);
+        assert!(ours.keys().copied().eq([1, 3]));
+        assert!(rest.keys().copied().eq([4, 5]));
+    }
+
+    #[test]
+    fn split_off_and_append() {
+        check_split_and_append::<Unbalanced>();
+        check_split_and_append::<Avl>();
+        check_split_and_append::<RedBlack>();
+    }
+
+    /// Removes ranges of every bound kind from sets of B, checking against a
+    /// BTreeSet
+    fn check_remove_range<B: Checked>() {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+        use std::ops::RangeBounds;
+
+        let mut rng = Rng(0x4a46);
+        let values: BTreeSet<u64> = (0..400).map(|_| rng.below(1_000)).collect();
+        for _ in 0..200 {
+            let (start, end) = (rng.below(1_100), rng.below(1_100));
+            let bound = |v: u64, kind: u64| match kind {
+                0 => Included(v),
+                1 => Excluded(v),
+                _ => Unbounded,
+            };
+            let range = (bound(start, rng.below(3)), bound(end, rng.below(3)));
+            let mut set: TreeSet<u64, B> = values.iter().copied().collect();
+            let mut expected = values.clone();
+            expected.retain(|v| !range.contains(v));
+            assert_eq!(set.remove_range(range), values.len() - expected.len());
+            assert!(set.iter().eq(expected.iter()));
+            assert_eq!(set.len(), expected.len());
+            B::check(&set.root);
+        }
+
+        let mut set: TreeSet<u64, B> = (0..100).collect();
+        assert_eq!(set.remove_range(40..40), 0);
+        assert_eq!(set.remove_range(200..), 0);
+        assert_eq!(set.remove_range(90..), 10);
+        assert_eq!(set.remove_range(..10), 10);
+        assert_eq!(set.remove_range(20..=29), 10);
+        assert!(set.iter().copied().eq((10..20).chain(30..90)));
+        assert_eq!(set.remove_range(..), 70);
+        assert!(set.is_empty());
+        B::check(&set.root);
+
+        let mut map: TreeMap<u64, u64, B> = (0..50).map(|k| (k, k * k)).collect();
+        assert_eq!(map.remove_range(5..45), 40);
+        assert!(map.keys().copied().eq((0..5).chain(45..50)));
+        assert!(map.iter().all(|(k, v)| *v == k * k));
+    }
+
+    #[test]
+    fn remove_range() {
+        check_remove_range::<Unbalanced>();
+        check_remove_range::<Avl>();
+        check_remove_range::<RedBlack>();
+    }
+
+    /// Removes values from sets and maps of B by predicate
+    fn check_retain_drain_extract<B: Checked>() {
+        let mut set: TreeSet<u32, B> = (0..100).collect();
+        set.retain(|v| v % 3 != 0);
+        assert!(set.iter().copied().eq((0..100).filter(|v| v % 3 != 0)));
+        assert_eq!(set.len(), 66);
+        B::check(&set.root);
+
+        let extracted: Vec<u32> = set.extract_if(|v| v % 2 == 0).collect();
+        assert!(extracted
+            .into_iter()
+            .eq((0..100).filter(|v| v % 6 == 2 || v % 6 == 4)));
+        assert!(set
+            .iter()
+            .copied()
+            .eq((0..100).filter(|v| v % 6 == 1 || v % 6 == 5)));
+        assert_eq!(set.len(), 33);
+        B::check(&set.root);
+
+        // Dropping the iterator early leaves the values it has not reached
+        {
+            let mut extract = set.extract_if(|v| *v > 10);
+            assert_eq!(extract.next(), Some(11));
+            assert_eq!(extract.next(), Some(13));
+        }
+        let expected = (0..100).filter(|v| (v % 6 == 1 || v % 6 == 5) && *v != 11 && *v != 13);
+        assert!(set.iter().copied().eq(expected));
+        B::check(&set.root);
+        // Nothing is removed unless the iterator advances
+        set.extract_if(|_| true);
+        assert_eq!(set.len(), 31);
+
+        let drained: Vec<u32> = set.drain().take(3).collect();
+        assert_eq!(drained, [1, 5, 7]);
+        assert!(set.is_empty());
+        set.insert(4);
+        assert!(set.drain().eq([4]));
+        set.retain(|_| true);
+        assert_eq!(set.extract_if(|_| true).next(), None);
+
+        let mut map: TreeMap<u32, u32, B> = (0..20).map(|k| (k, k)).collect();
+        map.retain(|k, v| {
+            *v *= 10;
+            k % 4 != 0
+        });
+        assert_eq!(map.len(), 15);
+        let extracted: Vec<(u32, u32)> = map
+            .extract_if(|k, v| {
+                *v += 1;
+                k % 2 == 0
+            })
+            .collect();
+        assert_eq!(
+            extracted,
+            [(2, 21), (6, 61), (10, 101), (14, 141), (18, 181)]
+        );
+        assert!(map.iter().all(|(k, v)| k % 2 == 1 && *v == k * 10 + 1));
+        B::check(&map.root);
+        assert_eq!(map.drain().len(), 10);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn retain_drain_and_extract_if() {
+        check_retain_drain_extract::<Unbalanced>();
+        check_retain_drain_extract::<Avl>();
+        check_retain_drain_extract::<RedBlack>();
+    }
+
+    /// Builds sets of B from sorted input of every length up to a few levels
+    /// deep, checking the trees
+    fn check_from_sorted<B: Checked>() {
+        for len in 0..130 {
+            let set: TreeSet<u32, B> = TreeSet::from_sorted_iter(0..len);
+            B::check(&set.root);
+            assert!(set.iter().copied().eq(0..len));
+            assert_eq!(set.len(), len as usize);
+        }
+        // Runs of equal values collapse to one
+        let doubled: Vec<u32> = (0..50).flat_map(|v| [v, v, v]).collect();
+        let set: TreeSet<u32, B> = TreeSet::from_sorted_vec(doubled);
+        B::check(&set.root);
+        assert!(set.iter().copied().eq(0..50));
+        assert_eq!(set.len(), 50);
+    }
+
+    #[test]
+    fn from_sorted_builds_valid_trees() {
+        check_from_sorted::<Unbalanced>();
+        check_from_sorted::<Avl>();
+        check_from_sorted::<RedBlack>();
+        for len in 0..300 {
+            RbSet::from_sorted_iter(0..len).check_invariants();
+        }
+    }
+
+    #[test]
+    fn from_sorted_keeps_the_first_of_equal_values() {
+        let values = [
+            Keyed(1, 'a'),
+            Keyed(1, 'b'),
+            Keyed(2, 'c'),
+            Keyed(3, 'd'),
+            Keyed(3, 'e'),
+        ];
+        let set = AvlSet::from_sorted_vec(values.to_vec());
+        assert!(set.iter().map(|value| value.1).eq(['a', 'c', 'd']));
+        let set: RbSet<Keyed> = values.into_iter().collect();
+        assert!(set.iter().map(|value| value.1).eq(['a', 'c', 'd']));
+    }
+
+    #[test]
+    #[should_panic(expected = "values are not in ascending order")]
+    fn from_sorted_rejects_unsorted_values() {
+        AvlSet::from_sorted_iter([1, 3, 2]);
+    }
+}
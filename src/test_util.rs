@@ -0,0 +1,119 @@
+use std::cell::Cell;
+use std::cmp;
+
+use crate::{rb, Avl, Balance, Bst, RedBlack, Unbalanced};
+
+thread_local! {
+    /// Number of descents of a tree begun on this thread (see count_descent)
+    pub(crate) static DESCENTS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Calls [f], returning its result along with the number of descents of a
+/// tree it made.
+pub(crate) fn descents<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = DESCENTS.with(Cell::get);
+    let result = f();
+    (result, DESCENTS.with(Cell::get) - before)
+}
+
+/// Small xorshift generator, so that randomized tests are reproducible
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    /// Returns a pseudo-random number less than [n]
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+/// Checks the height and size cached in every node below [link], panicking
+/// if any is wrong. Returns the height.
+pub(crate) fn check_sizes<E, A>(link: &Option<Box<Bst<E, A>>>) -> usize {
+    match link {
+        None => 0,
+        Some(node) => {
+            let height = 1 + cmp::max(check_sizes(&node.left), check_sizes(&node.right));
+            assert_eq!(node.height, height);
+            let size = 1 + Bst::size_of(&node.left) + Bst::size_of(&node.right);
+            assert_eq!(node.size, size);
+            height
+        }
+    }
+}
+
+/// Checks the AVL invariants below [link], along with the height and size
+/// cached in every node, panicking if any is violated. Returns the height.
+pub(crate) fn check_avl<E, A>(link: &Option<Box<Bst<E, A>>>) -> usize {
+    match link {
+        None => 0,
+        Some(node) => {
+            let left = check_avl(&node.left);
+            let right = check_avl(&node.right);
+            assert!(left.abs_diff(right) <= 1, "subtree heights differ by two");
+            assert_eq!(node.height, 1 + cmp::max(left, right));
+            let size = 1 + Bst::size_of(&node.left) + Bst::size_of(&node.right);
+            assert_eq!(node.size, size);
+            node.height
+        }
+    }
+}
+
+/// Greatest height of an AVL tree of [len] values
+pub(crate) fn avl_height_bound(len: usize) -> usize {
+    (1.4405 * ((len + 2) as f64).log2() - 0.3277) as usize
+}
+
+/// A balancing mode whose invariants tests can check
+pub(crate) trait Checked: Balance + Clone {
+    /// Checks the tree at [root], panicking if it is not a valid tree of
+    /// this mode or its cached heights and sizes are wrong
+    fn check<E, A>(root: &Option<Box<Bst<E, A>>>);
+}
+
+impl Checked for Unbalanced {
+    fn check<E, A>(root: &Option<Box<Bst<E, A>>>) {
+        check_sizes(root);
+    }
+}
+
+impl Checked for Avl {
+    fn check<E, A>(root: &Option<Box<Bst<E, A>>>) {
+        check_avl(root);
+    }
+}
+
+impl Checked for RedBlack {
+    fn check<E, A>(root: &Option<Box<Bst<E, A>>>) {
+        check_sizes(root);
+        assert!(root.as_ref().is_none_or(|root| !root.red), "root is red");
+        rb::check(root);
+    }
+}
+
+/// A value ordered by its key alone, so that tests can tell apart values that
+/// compare equal by their tags
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Keyed(pub(crate) u32, pub(crate) char);
+
+impl PartialEq for Keyed {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Keyed {}
+
+impl PartialOrd for Keyed {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Keyed {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}